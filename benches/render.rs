@@ -0,0 +1,83 @@
+//! Benchmarks the template engine that `silo apply` uses. Silo has no library
+//! target, so the modules the engine depends on are included directly
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/config/mod.rs"]
+mod config;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/scripting/mod.rs"]
+mod scripting;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/state.rs"]
+mod state;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/templating/mod.rs"]
+mod templating;
+#[allow(dead_code, unused_imports)]
+#[path = "../src/utils.rs"]
+mod utils;
+
+use std::{collections::HashMap, fs, path::PathBuf};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use rayon::ThreadPoolBuilder;
+use tempfile::TempDir;
+
+use config::SiloConfig;
+use templating::Engine;
+
+const TEMPLATES: usize = 300;
+
+/// Creates a few hundred templates and returns their paths
+fn setup() -> (TempDir, Vec<PathBuf>) {
+    let dir = tempfile::tempdir().unwrap();
+    let mut paths = Vec::new();
+
+    for i in 0..TEMPLATES {
+        let template = format!(
+            "# file {i} for {{{{ctx.name}}}} on {{{{system.hostname}}}}\n\
+             {{{{#each ctx.items}}}}\n\
+             item {{{{@index}}}} = {{{{this}}}}\n\
+             {{{{/each}}}}\n\
+             {{{{#if ctx.name}}}}named{{{{else}}}}anonymous{{{{/if}}}}\n"
+        );
+        let path = dir.path().join(format!("file{i}.conf.tmpl"));
+        fs::write(&path, template.repeat(20)).unwrap();
+        paths.push(path);
+    }
+
+    (dir, paths)
+}
+
+/// Compiles and renders all templates with a new engine like a single apply does
+fn render(config: &SiloConfig, paths: &[PathBuf], userdata: &HashMap<String, toml::Value>) {
+    let mut engine = Engine::new(config);
+    engine.register_files(paths).unwrap();
+    engine.render_files(paths, userdata).unwrap();
+}
+
+fn render_templates(c: &mut Criterion) {
+    let (_dir, paths) = setup();
+    let config = SiloConfig::default();
+    let userdata = HashMap::from([
+        (String::from("name"), toml::Value::from("bench")),
+        (
+            String::from("items"),
+            toml::Value::from(vec!["a", "b", "c", "d"]),
+        ),
+    ]);
+    let single_thread = ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+
+    let mut group = c.benchmark_group("render 300 templates");
+    group.sample_size(20);
+    group.bench_function("sequential", |b| {
+        b.iter(|| single_thread.install(|| render(&config, &paths, &userdata)))
+    });
+    group.bench_function("parallel", |b| {
+        b.iter(|| render(&config, &paths, &userdata))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, render_templates);
+criterion_main!(benches);
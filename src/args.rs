@@ -2,6 +2,8 @@ use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
 
+use crate::config::ApplyPolicy;
+
 #[derive(Clone, Debug, Parser)]
 pub struct Args {
     #[arg(short, long)]
@@ -9,6 +11,10 @@ pub struct Args {
 
     #[arg(short, long, default_value = default_repo() )]
     pub repo: PathBuf,
+
+    /// The profile to apply. Defaults to the profile mapped to the hostname
+    #[arg(short, long, env = "SILO_PROFILE", global = true)]
+    pub profile: Option<String>,
     /// The silo command to execute
     #[command(subcommand)]
     pub command: Command,
@@ -19,13 +25,41 @@ pub enum Command {
     /// Initialize a silo repository
     Init(InitArgs),
     /// Applies the configuration stored in a silo repo
-    Apply,
+    Apply(ApplyArgs),
+
+    /// Fetch the remote and fast-forward the repo
+    #[command(alias = "pull")]
+    Update(UpdateArgs),
+
+    /// Stage and commit all changes in the repo
+    Commit(CommitArgs),
+
+    /// Push the repo to its remote
+    Push,
+
+    /// Add an existing file to the repo
+    Add(AddArgs),
+
+    /// Copy changes made to applied files back into the repo
+    ReAdd(ReAddArgs),
+
+    /// Show the changes that would be applied
+    Diff(DiffArgs),
+
+    /// Show the state of all managed files compared to the last apply
+    Status,
+
+    /// Restore files from the backups made during apply
+    Restore(RestoreArgs),
 
     /// Print the entire context available to templates
     Context,
 
+    /// Print the merged configuration
+    Config(ConfigArgs),
+
     /// Print the path of the repo
-    Repo,
+    Repo(RepoArgs),
 }
 
 #[derive(Clone, Debug, Parser)]
@@ -35,6 +69,136 @@ pub struct InitArgs {
     pub remote: Option<String>,
 }
 
+#[derive(Clone, Debug, Default, Parser)]
+pub struct ApplyArgs {
+    /// Only apply files matching the given destination paths,
+    /// repo relative source paths or globs
+    #[arg()]
+    pub targets: Vec<String>,
+
+    /// Print the planned changes without writing anything or running hooks
+    #[arg(long)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub policy: PolicyArgs,
+}
+
+/// Flags that select how changed files are handled on apply
+#[derive(Clone, Debug, Default, Parser)]
+pub struct PolicyArgs {
+    /// Overwrite all changed files without asking
+    #[arg(short, long, group = "policy")]
+    pub yes: bool,
+
+    /// Keep files that were changed locally without asking
+    #[arg(long, group = "policy")]
+    pub no_overwrite: bool,
+
+    /// Only create files that don't exist yet
+    #[arg(long, group = "policy")]
+    pub only_new: bool,
+}
+
+impl PolicyArgs {
+    /// Returns the apply policy selected with the flags
+    pub fn policy(&self) -> Option<ApplyPolicy> {
+        if self.yes {
+            Some(ApplyPolicy::Overwrite)
+        } else if self.no_overwrite {
+            Some(ApplyPolicy::NoOverwrite)
+        } else if self.only_new {
+            Some(ApplyPolicy::OnlyNew)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct UpdateArgs {
+    /// Apply the repo after updating it
+    #[arg(long)]
+    pub apply: bool,
+
+    /// How changed files are handled when the repo is applied
+    #[command(flatten)]
+    pub policy: PolicyArgs,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct CommitArgs {
+    /// The commit message. Defaults to a list of the changed files
+    #[arg(short, long)]
+    pub message: Option<String>,
+
+    /// Push the repo after committing
+    #[arg(long)]
+    pub push: bool,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct AddArgs {
+    /// The file to add
+    #[arg()]
+    pub path: PathBuf,
+
+    /// Store the file as a template
+    #[arg(short, long)]
+    pub template: bool,
+
+    /// Add all files in a directory
+    #[arg(short, long)]
+    pub recursive: bool,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct ReAddArgs {
+    /// Only re-add the given files or directories
+    #[arg()]
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct DiffArgs {
+    /// Use the configured diff tool instead of printing a unified diff
+    #[arg(long)]
+    pub tool: bool,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct RestoreArgs {
+    /// The backup run to restore from. Defaults to the latest run
+    #[arg(long)]
+    pub run: Option<String>,
+
+    /// List all backup runs and their files
+    #[arg(long)]
+    pub list: bool,
+
+    /// Only restore the given file
+    #[arg()]
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct ConfigArgs {
+    /// Only print the given key. Nested keys are separated by dots
+    #[arg()]
+    pub key: Option<String>,
+
+    /// Show which config file or provider set each value
+    #[arg(long)]
+    pub explain: bool,
+}
+
+#[derive(Clone, Debug, Parser)]
+pub struct RepoArgs {
+    /// Print the branch, uncommitted changes and how far the repo is ahead or behind its remote
+    #[arg(long)]
+    pub status: bool,
+}
+
 fn default_repo() -> &'static str {
     lazy_static::lazy_static! {
         static ref DEFAULT_REPO: String = dirs::data_dir()
@@ -0,0 +1,159 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use miette::{bail, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{state::data_dir, utils::Describe};
+
+/// Backups of all files that were overwritten during a single apply run
+pub struct BackupRun {
+    pub id: String,
+    dir: PathBuf,
+    manifest: BackupManifest,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct BackupManifest {
+    /// Maps the original paths to the file names in the backup directory
+    files: BTreeMap<PathBuf, String>,
+}
+
+impl BackupRun {
+    /// Creates a new run. The backup directory is only created once
+    /// the first file is backed up
+    pub fn new() -> Result<Self> {
+        let mut id = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .into_diagnostic()?
+            .as_secs();
+
+        while backups_dir()?.join(id.to_string()).exists() {
+            id += 1;
+        }
+        let id = id.to_string();
+
+        Ok(Self {
+            dir: backups_dir()?.join(&id),
+            id,
+            manifest: BackupManifest::default(),
+        })
+    }
+
+    /// Opens an existing run
+    pub fn open(id: &str) -> Result<Self> {
+        let dir = backups_dir()?.join(id);
+        let manifest_path = dir.join("manifest.json");
+
+        if !manifest_path.exists() {
+            bail!("There is no backup run with the id {id}");
+        }
+        let contents = fs::read_to_string(&manifest_path)
+            .with_describe(|| format!("reading backup manifest {manifest_path:?}"))?;
+        let manifest = serde_json::from_str(&contents)
+            .with_describe(|| format!("parsing backup manifest {manifest_path:?}"))?;
+
+        Ok(Self {
+            id: id.to_owned(),
+            dir,
+            manifest,
+        })
+    }
+
+    /// Returns the ids of all existing runs, oldest first
+    pub fn list() -> Result<Vec<String>> {
+        let dir = backups_dir()?;
+
+        if !dir.exists() {
+            return Ok(Vec::new());
+        }
+        let mut ids = Vec::new();
+
+        for entry in fs::read_dir(&dir).describe("reading backup directory")? {
+            let entry = entry.into_diagnostic()?;
+
+            if entry.path().join("manifest.json").exists() {
+                ids.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        ids.sort_by_key(|id| id.parse::<u64>().unwrap_or_default());
+
+        Ok(ids)
+    }
+
+    /// Returns all files stored in this run
+    pub fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.manifest.files.keys()
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.manifest.files.contains_key(path)
+    }
+
+    /// Copies the current contents of `path` into the backup directory
+    pub fn backup(&mut self, path: &Path) -> Result<()> {
+        if self.contains(path) {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir).describe("creating backup directory")?;
+        let file_name = format!(
+            "{}-{}",
+            self.manifest.files.len(),
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        fs::copy(path, self.dir.join(&file_name))
+            .with_describe(|| format!("creating backup of {path:?}"))?;
+        self.manifest.files.insert(path.to_owned(), file_name);
+        log::debug!("Backed up {path:?} in run {}", self.id);
+
+        let manifest =
+            serde_json::to_string_pretty(&self.manifest).describe("serializing backup manifest")?;
+        fs::write(self.dir.join("manifest.json"), manifest).describe("writing backup manifest")
+    }
+
+    /// Restores the backed up contents of `path`
+    pub fn restore(&self, path: &Path) -> Result<()> {
+        let Some(file_name) = self.manifest.files.get(path) else {
+            bail!(
+                "The file {path:?} is not part of the backup run {}",
+                self.id
+            );
+        };
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_describe(|| format!("creating directory {parent:?}"))?;
+        }
+        fs::copy(self.dir.join(file_name), path)
+            .with_describe(|| format!("restoring {path:?} from backup run {}", self.id))?;
+        log::info!("Restored {path:?}");
+
+        Ok(())
+    }
+
+    /// Removes the oldest runs so that at most `retention` runs are kept.
+    /// The latest run is always kept
+    pub fn prune(retention: usize) -> Result<()> {
+        let ids = Self::list()?;
+
+        for id in ids.iter().take(ids.len().saturating_sub(retention.max(1))) {
+            log::debug!("Removing backup run {id}");
+            fs::remove_dir_all(backups_dir()?.join(id))
+                .with_describe(|| format!("removing backup run {id}"))?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns true if no file has been backed up in this run
+    pub fn is_empty(&self) -> bool {
+        self.manifest.files.is_empty()
+    }
+}
+
+fn backups_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("backups"))
+}
@@ -0,0 +1,512 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use figment::{
+    providers::{Env, Format, Serialized, Toml},
+    value::{Dict, Map, Value},
+    Figment, Metadata, Profile, Provider,
+};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use mlua::LuaSerdeExt;
+use serde::{Deserialize, Serialize};
+use which::which;
+
+use crate::{
+    scripting::{create_config_lua, create_lua},
+    templating::{ScriptHelpers, SystemData},
+    utils::Describe,
+};
+
+pub use schema::SchemaEntry;
+
+pub mod schema;
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SiloConfig {
+    /// Diff tool used to display file differences
+    pub diff_tool: String,
+    /// Merge tool used when both the repo and the target changed.
+    /// It is called with the base, local, remote and merged file paths
+    pub merge_tool: Option<String>,
+    /// Whether files are backed up before being overwritten
+    pub backup: bool,
+    /// The number of backup runs that are kept. The latest run is always kept
+    pub backup_retention: usize,
+    /// How changed files are handled on apply
+    pub apply_policy: ApplyPolicy,
+    /// Deploy plain files as symlinks into the repo unless
+    /// a root directory specifies otherwise
+    pub link: bool,
+    /// Fail rendering templates that use undefined variables
+    pub strict_templates: bool,
+    /// The active profile
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Profiles that are selected automatically by hostname
+    pub profiles: HashMap<String, String>,
+    /// Declarations of the additional config options. The key is namespaced
+    /// so that it doesn't collide with an additional option called `schema`
+    #[serde(rename = "silo_schema")]
+    pub schema: HashMap<String, SchemaEntry>,
+    /// Additional config options
+    #[serde(flatten)]
+    pub userdata: HashMap<String, toml::Value>,
+    /// Template helpers returned by the repo's `silo.config.lua`
+    #[serde(skip)]
+    pub script_helpers: Option<ScriptHelpers>,
+}
+
+impl Default for SiloConfig {
+    fn default() -> Self {
+        Self {
+            diff_tool: detect_difftool(),
+            merge_tool: None,
+            backup: true,
+            backup_retention: 10,
+            apply_policy: ApplyPolicy::Prompt,
+            link: false,
+            strict_templates: false,
+            profile: None,
+            profiles: HashMap::new(),
+            schema: HashMap::new(),
+            userdata: HashMap::new(),
+            script_helpers: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApplyPolicy {
+    /// Ask before changing an existing file
+    Prompt,
+    /// Overwrite all existing files without asking
+    Overwrite,
+    /// Keep existing files that were changed locally
+    NoOverwrite,
+    /// Only create files that don't exist yet
+    OnlyNew,
+}
+
+fn detect_difftool() -> String {
+    ["difft", "delta", "diff"]
+        .into_iter()
+        .filter(|t| which(t).is_ok())
+        .map(String::from)
+        .next()
+        .unwrap_or_else(|| String::from("diff"))
+}
+
+/// A config value together with the provider that set it
+pub struct ExplainedValue {
+    pub key: String,
+    pub value: Value,
+    pub provider: String,
+}
+
+/// Serialized values with a name that describes where they came from
+struct Named<T> {
+    metadata: Metadata,
+    value: Serialized<T>,
+}
+
+impl<T> Named<T> {
+    fn new(name: &'static str, path: Option<&Path>, value: Serialized<T>) -> Self {
+        let mut metadata = Metadata::named(name);
+
+        if let Some(path) = path {
+            metadata = metadata.source(path);
+        }
+        Self { metadata, value }
+    }
+}
+
+impl<T: Serialize> Provider for Named<T> {
+    fn metadata(&self) -> Metadata {
+        self.metadata.clone()
+    }
+
+    fn data(&self) -> figment::Result<Map<Profile, Dict>> {
+        self.value.data()
+    }
+}
+
+/// Reads the merged configuration of all config files, asks for
+/// missing required values and validates it against the schema
+pub fn read_config(repo: &Path, profile: Option<&str>) -> Result<SiloConfig> {
+    let (figment, script_helpers) = load_figment(&config_dir(), repo, profile)?;
+    let mut config = SiloConfig {
+        script_helpers,
+        ..figment
+            .extract()
+            .into_diagnostic()
+            .context("parsing config file")?
+    };
+    schema::prompt_missing(
+        &config.schema,
+        &mut config.userdata,
+        &repo.join("repo.local.toml"),
+    )?;
+    schema::validate(&config.schema, &mut config.userdata)?;
+
+    Ok(config)
+}
+
+/// Returns all leaf values under the given key with the provider that set them
+pub fn explain(figment: &Figment, key: Option<&str>) -> Result<Vec<ExplainedValue>> {
+    let value = match key {
+        Some(key) => figment.find_value(key).describe("finding config key")?,
+        None => Value::from(
+            figment
+                .extract::<Dict>()
+                .into_diagnostic()
+                .context("parsing config file")?
+                .into_keys()
+                .filter_map(|k| Some((k.clone(), figment.find_value(&k).ok()?)))
+                .collect::<Dict>(),
+        ),
+    };
+    let mut values = Vec::new();
+    collect_leaves(figment, key.unwrap_or_default(), value, &mut values);
+
+    Ok(values)
+}
+
+fn collect_leaves(figment: &Figment, key: &str, value: Value, values: &mut Vec<ExplainedValue>) {
+    match value {
+        Value::Dict(_, dict) if !dict.is_empty() => {
+            for (child, value) in dict {
+                let child_key = if key.is_empty() {
+                    child
+                } else {
+                    format!("{key}.{child}")
+                };
+                collect_leaves(figment, &child_key, value, values);
+            }
+        }
+        value => {
+            let provider = match figment.get_metadata(value.tag()) {
+                Some(Metadata {
+                    name,
+                    source: Some(source),
+                    ..
+                }) => format!("{name} {source}"),
+                Some(metadata) => metadata.name.to_string(),
+                None => String::from("unknown"),
+            };
+            values.push(ExplainedValue {
+                key: key.to_owned(),
+                value,
+                provider,
+            });
+        }
+    }
+}
+
+/// Merges the configuration file from the user config directory
+/// with overrides from the `repo.toml` file,
+/// the overlay of the active profile,
+/// the `repo.<hostname>.toml` file,
+/// the `repo.local.toml` config file,
+/// the options changed by the repo's lua configs,
+/// the options changed by the user's lua config
+/// and environment variables prefixed with `SILO_``
+pub fn config_figment(repo: &Path, profile: Option<&str>) -> Result<Figment> {
+    Ok(load_figment(&config_dir(), repo, profile)?.0)
+}
+
+fn config_dir() -> PathBuf {
+    dirs::config_dir().unwrap()
+}
+
+/// Builds the config figment with the user config from `conf_dir`
+/// and returns the template helpers of the repo's `silo.config.lua` with it
+fn load_figment(
+    conf_dir: &Path,
+    repo: &Path,
+    profile: Option<&str>,
+) -> Result<(Figment, Option<ScriptHelpers>)> {
+    let default_config = conf_dir.join("silo.config.lua");
+    let old_config = conf_dir.join("silo.toml");
+
+    if !default_config.exists() {
+        let mut lines = vec![
+            "local silo = require 'silo'".to_owned(),
+            "local utils = require 'utils'".to_owned(),
+            "local config = silo.default_config".to_owned(),
+        ];
+        if old_config.exists() {
+            lines.push("".to_owned());
+            lines.push("-- merge with old toml config".to_owned());
+            lines.push(format!(
+                "config = utils.merge(config, utils.load_toml {old_config:?})"
+            ));
+            lines.push("config = utils.merge(config, config.template_context)".to_string())
+        }
+        lines.push("-- Changes can be added to the `config` object".to_owned());
+        lines.push("".to_owned());
+        lines.push("return config".to_owned());
+
+        fs::write(&default_config, lines.join("\n")).describe("Writing default config")?
+    }
+
+    let mut base = Figment::from(Named::new(
+        "defaults",
+        None,
+        Serialized::defaults(SiloConfig::default()),
+    ))
+    .merge(Toml::file(old_config))
+    .merge(Toml::file(repo.join("repo.toml")));
+    let schema_file = repo.join("silo.schema.toml");
+
+    if schema_file.exists() {
+        base = base.merge(Named::new(
+            "schema file",
+            Some(&schema_file),
+            Serialized::default("silo_schema", read_schema(&schema_file)?),
+        ));
+    }
+    let hostname = SystemData::default().hostname;
+    let with_toml_overrides = |builder: Figment| {
+        builder
+            .merge(Toml::file(repo.join(format!("repo.{hostname}.toml"))))
+            .merge(Toml::file(repo.join("repo.local.toml")))
+    };
+
+    // the user config is evaluated before the profile is resolved as it can select the profile
+    let toml_config = extract(&with_toml_overrides(base.clone()))?;
+    let handed = SiloConfig {
+        profile: profile.map(String::from).or(toml_config.profile.clone()),
+        ..toml_config
+    };
+    let user_lua = Named::new(
+        "user lua config",
+        Some(&default_config),
+        Serialized::globals(read_lua_config(&default_config, &handed)?.0),
+    );
+
+    // the profile is resolved before the repo's lua configs are evaluated
+    // so that they see the active profile in `silo.profile`
+    let config = extract(
+        &with_toml_overrides(base.clone())
+            .merge(&user_lua)
+            .merge(Env::prefixed("SILO_")),
+    )?;
+    let profile = profile
+        .map(String::from)
+        .or(config.profile)
+        .or_else(|| config.profiles.get(&hostname).cloned());
+    let lua_config = SiloConfig {
+        profile: profile.clone(),
+        ..Default::default()
+    };
+    let mut builder = base;
+
+    if let Some(name) = &profile {
+        log::debug!("Using profile {name}");
+        let toml_overlay = repo.join("profiles").join(format!("{name}.toml"));
+        let lua_overlay = repo.join("profiles").join(format!("{name}.lua"));
+
+        if !toml_overlay.exists() && !lua_overlay.exists() {
+            bail!(
+                help = format!("create {toml_overlay:?} or {lua_overlay:?}"),
+                "The profile {name} doesn't exist"
+            );
+        }
+        builder = builder.merge(Toml::file(toml_overlay));
+
+        if lua_overlay.exists() {
+            builder = builder.merge(Named::new(
+                "profile lua config",
+                Some(&lua_overlay),
+                Serialized::globals(read_lua_overlay(&lua_overlay, &lua_config)?),
+            ));
+        }
+    }
+    builder = with_toml_overrides(builder);
+    let repo_defaults = repo.join("silo.config.lua");
+    let mut script_helpers = None;
+
+    if repo_defaults.exists() {
+        let handed = SiloConfig {
+            profile: profile.clone(),
+            ..extract(&builder)?
+        };
+        let (options, helpers) = read_lua_config(&repo_defaults, &handed)?;
+        script_helpers = helpers;
+        builder = builder.merge(Named::new(
+            "repo lua config",
+            Some(&repo_defaults),
+            Serialized::globals(options),
+        ));
+    }
+    let host_defaults = repo.join(format!("silo.{hostname}.config.lua"));
+
+    if host_defaults.exists() {
+        builder = builder.merge(Named::new(
+            "host lua config",
+            Some(&host_defaults),
+            Serialized::globals(read_lua_overlay(&host_defaults, &lua_config)?),
+        ));
+    }
+    builder = builder.merge(&user_lua).merge(Env::prefixed("SILO_"));
+
+    if let Some(name) = profile {
+        builder = builder.merge(Named::new(
+            "active profile",
+            None,
+            Serialized::default("profile", name),
+        ));
+    }
+
+    Ok((builder, script_helpers))
+}
+
+fn extract(figment: &Figment) -> Result<SiloConfig> {
+    figment
+        .extract()
+        .into_diagnostic()
+        .context("parsing config file")
+}
+
+fn read_schema(path: &Path) -> Result<HashMap<String, SchemaEntry>> {
+    let contents = fs::read_to_string(path).with_describe(|| format!("reading schema {path:?}"))?;
+
+    toml::from_str(&contents).with_describe(|| format!("parsing schema {path:?}"))
+}
+
+/// Evaluates a config script that builds on the handed config in `silo.default_config`
+/// and returns the whole config. Only the options that differ from the handed config
+/// are returned, so that options set by earlier config files aren't reset.
+/// The functions of a `helpers` table are kept in the lua state and returned as template helpers
+fn read_lua_config(
+    path: &Path,
+    handed: &SiloConfig,
+) -> Result<(serde_json::Value, Option<ScriptHelpers>)> {
+    let lua = create_config_lua(handed)?;
+    let result = lua
+        .load(path)
+        .eval()
+        .with_describe(|| format!("evaluating config script {path:?}"))?;
+    let mut helpers = None;
+
+    // template helpers are functions that are loaded by the template engine
+    if let mlua::Value::Table(table) = &result {
+        if let Some(functions) = table
+            .raw_get::<_, Option<mlua::Table>>("helpers")
+            .describe("reading template helpers")?
+        {
+            helpers = Some(ScriptHelpers::store(&lua, functions, path)?);
+        }
+        table
+            .raw_remove("helpers")
+            .describe("removing template helpers")?;
+    }
+    let cfg: SiloConfig = lua
+        .from_value(result)
+        .describe("deserializing lua config value")?;
+    let handed = serde_json::to_value(handed).describe("serializing config")?;
+    let mut value = serde_json::to_value(cfg).describe("serializing config")?;
+
+    if let serde_json::Value::Object(options) = &mut value {
+        options.retain(|key, value| handed.get(key) != Some(value));
+    }
+
+    Ok((value, helpers.map(|names| ScriptHelpers::new(lua, names))))
+}
+
+/// Evaluates an overlay script that returns a table with the options it overrides
+fn read_lua_overlay(path: &Path, config: &SiloConfig) -> Result<serde_json::Value> {
+    let lua = create_lua(config)?;
+    let result = lua
+        .load(path)
+        .eval()
+        .with_describe(|| format!("evaluating config script {path:?}"))?;
+
+    lua.from_value(result)
+        .describe("deserializing lua config value")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::templating::SystemData;
+
+    use super::{load_figment, SiloConfig};
+
+    #[test]
+    fn it_lets_overlays_override_builtin_options() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        let config_dir = dir.path().join("config");
+        fs::create_dir_all(repo.join("profiles")).unwrap();
+        fs::create_dir_all(&config_dir).unwrap();
+
+        let hostname = SystemData::default().hostname;
+        fs::write(repo.join("repo.toml"), "link = false\nbackup = true").unwrap();
+        fs::write(repo.join("profiles").join("work.toml"), "link = true").unwrap();
+        fs::write(repo.join(format!("repo.{hostname}.toml")), "backup = false").unwrap();
+        fs::write(
+            repo.join("silo.config.lua"),
+            "local silo = require 'silo'\n\
+             local config = silo.default_config\n\
+             config.strict_templates = true\n\
+             return config",
+        )
+        .unwrap();
+
+        let config: SiloConfig = load_figment(&config_dir, &repo, Some("work"))
+            .unwrap()
+            .0
+            .extract()
+            .unwrap();
+        assert!(config.link);
+        assert!(!config.backup);
+        assert!(config.strict_templates);
+        assert_eq!(config.profile.as_deref(), Some("work"));
+    }
+
+    #[test]
+    fn it_lets_lua_configs_restore_default_values() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        let config_dir = dir.path().join("config");
+        fs::create_dir_all(&repo).unwrap();
+        fs::create_dir_all(&config_dir).unwrap();
+
+        fs::write(
+            repo.join("repo.toml"),
+            "link = true
+backup = false",
+        )
+        .unwrap();
+        fs::write(
+            repo.join("silo.config.lua"),
+            "local silo = require 'silo'\n\
+             local config = silo.default_config\n\
+             config.link = false\n\
+             return config",
+        )
+        .unwrap();
+        fs::write(
+            config_dir.join("silo.config.lua"),
+            "local silo = require 'silo'\n\
+             local config = silo.default_config\n\
+             config.backup = true\n\
+             return config",
+        )
+        .unwrap();
+
+        let config: SiloConfig = load_figment(&config_dir, &repo, None)
+            .unwrap()
+            .0
+            .extract()
+            .unwrap();
+        assert!(!config.link);
+        assert!(config.backup);
+    }
+}
@@ -0,0 +1,362 @@
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    io::{self, IsTerminal},
+    path::Path,
+};
+
+use dialoguer::{Confirm, Input};
+use miette::{Diagnostic, Result};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::utils::Describe;
+
+/// The declaration of a config key
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct SchemaEntry {
+    /// The type the value must have
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub value_type: Option<ValueType>,
+    /// The value that is used if the key isn't set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<toml::Value>,
+    /// Whether the key must be set
+    pub required: bool,
+    /// What the key is used for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    String,
+    Integer,
+    Float,
+    Boolean,
+    Array,
+    Table,
+}
+
+impl ValueType {
+    fn matches(&self, value: &toml::Value) -> bool {
+        matches!(
+            (self, value),
+            (ValueType::String, toml::Value::String(_))
+                | (ValueType::Integer, toml::Value::Integer(_))
+                | (
+                    ValueType::Float,
+                    toml::Value::Float(_) | toml::Value::Integer(_)
+                )
+                | (ValueType::Boolean, toml::Value::Boolean(_))
+                | (ValueType::Array, toml::Value::Array(_))
+                | (ValueType::Table, toml::Value::Table(_))
+        )
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ValueType::String => "string",
+            ValueType::Integer => "integer",
+            ValueType::Float => "float",
+            ValueType::Boolean => "boolean",
+            ValueType::Array => "array",
+            ValueType::Table => "table",
+        };
+        name.fmt(f)
+    }
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The config doesn't match the schema")]
+#[diagnostic(
+    code(silo::config::schema),
+    help("set the keys in `repo.local.toml` or change the schema")
+)]
+pub struct SchemaError {
+    #[related]
+    pub problems: Vec<SchemaProblem>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum SchemaProblem {
+    #[error("The required key `{key}` is missing")]
+    Missing {
+        key: String,
+        #[help]
+        description: Option<String>,
+    },
+    #[error("The key `{key}` must be of type {expected} but is of type {actual}")]
+    Mistyped {
+        key: String,
+        expected: ValueType,
+        actual: &'static str,
+        #[help]
+        description: Option<String>,
+    },
+    #[error("The key `{key}` can't be set because `{parent}` is not a table")]
+    NotATable { key: String, parent: String },
+}
+
+/// Fills in the defaults of missing keys and checks
+/// that all declared keys are set and have the right type
+pub fn validate(
+    schema: &HashMap<String, SchemaEntry>,
+    userdata: &mut HashMap<String, toml::Value>,
+) -> std::result::Result<(), SchemaError> {
+    let mut keys: Vec<_> = schema.keys().collect();
+    keys.sort();
+    let mut problems = Vec::new();
+
+    for key in keys {
+        let entry = &schema[key];
+
+        if get(userdata, key).is_none() {
+            if let Some(default) = &entry.default {
+                if let Err(problem) = insert(userdata, key, default.clone()) {
+                    problems.push(problem);
+                    continue;
+                }
+            }
+        }
+        match (get(userdata, key), entry.value_type) {
+            (None, _) if entry.required => problems.push(SchemaProblem::Missing {
+                key: key.to_owned(),
+                description: entry.description.clone(),
+            }),
+            (Some(value), Some(expected)) if !expected.matches(value) => {
+                problems.push(SchemaProblem::Mistyped {
+                    key: key.to_owned(),
+                    expected,
+                    actual: value.type_str(),
+                    description: entry.description.clone(),
+                })
+            }
+            _ => {}
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(SchemaError { problems })
+    }
+}
+
+/// Asks for the values of required keys that are missing and have no default
+/// and stores the answers in the given local config file.
+/// Does nothing if stdin is not a terminal
+pub fn prompt_missing(
+    schema: &HashMap<String, SchemaEntry>,
+    userdata: &mut HashMap<String, toml::Value>,
+    local_config: &Path,
+) -> Result<()> {
+    if !io::stdin().is_terminal() {
+        return Ok(());
+    }
+    let mut keys: Vec<_> = schema
+        .iter()
+        .filter(|(key, entry)| {
+            entry.required && entry.default.is_none() && get(userdata, key).is_none()
+        })
+        .collect();
+
+    if keys.is_empty() {
+        return Ok(());
+    }
+    keys.sort_by_key(|(key, _)| *key);
+    let mut local = read_local(local_config)?;
+    let mut answered = false;
+
+    for (key, entry) in keys {
+        let Some(value) = prompt_value(key, entry)? else {
+            continue;
+        };
+        insert(userdata, key, value.clone())?;
+        insert_nested(&mut local, key, key, value)?;
+        answered = true;
+    }
+
+    if answered {
+        write_local(local_config, &local)?;
+    }
+
+    Ok(())
+}
+
+/// Asks for the value of a key that templates use but the config doesn't set
+/// and stores the answer in the given local config file.
+/// Returns false without asking if stdin is not a terminal
+pub fn prompt_undeclared(
+    key: &str,
+    userdata: &mut HashMap<String, toml::Value>,
+    local_config: &Path,
+) -> Result<bool> {
+    if !io::stdin().is_terminal() {
+        return Ok(false);
+    }
+    let entry = SchemaEntry {
+        description: Some(String::from("used by a template")),
+        ..Default::default()
+    };
+    let Some(value) = prompt_value(key, &entry)? else {
+        return Ok(false);
+    };
+    let mut local = read_local(local_config)?;
+    insert(userdata, key, value.clone())?;
+    insert_nested(&mut local, key, key, value)?;
+    write_local(local_config, &local)?;
+
+    Ok(true)
+}
+
+fn read_local(local_config: &Path) -> Result<toml::Value> {
+    if !local_config.exists() {
+        return Ok(toml::Value::Table(Default::default()));
+    }
+    let contents =
+        fs::read_to_string(local_config).with_describe(|| format!("reading {local_config:?}"))?;
+
+    toml::from_str(&contents).with_describe(|| format!("parsing {local_config:?}"))
+}
+
+fn write_local(local_config: &Path, local: &toml::Value) -> Result<()> {
+    let contents = toml::to_string_pretty(local).describe("serializing local config")?;
+    fs::write(local_config, contents).with_describe(|| format!("writing {local_config:?}"))?;
+    log::info!("Saved the answers to {local_config:?}");
+
+    Ok(())
+}
+
+/// Asks for the value of a key. Returns `None` for types that can't be entered
+fn prompt_value(key: &str, entry: &SchemaEntry) -> Result<Option<toml::Value>> {
+    let prompt = match &entry.description {
+        Some(description) => format!("{key} ({description})"),
+        None => key.to_owned(),
+    };
+    let value = match entry.value_type {
+        Some(ValueType::Boolean) => toml::Value::Boolean(
+            Confirm::new()
+                .with_prompt(prompt)
+                .interact()
+                .describe("prompting for value")?,
+        ),
+        Some(ValueType::Integer) => toml::Value::Integer(
+            Input::new()
+                .with_prompt(prompt)
+                .interact_text()
+                .describe("prompting for value")?,
+        ),
+        Some(ValueType::Float) => toml::Value::Float(
+            Input::new()
+                .with_prompt(prompt)
+                .interact_text()
+                .describe("prompting for value")?,
+        ),
+        Some(ValueType::String) | None => toml::Value::String(
+            Input::new()
+                .with_prompt(prompt)
+                .interact_text()
+                .describe("prompting for value")?,
+        ),
+        Some(ValueType::Array | ValueType::Table) => return Ok(None),
+    };
+
+    Ok(Some(value))
+}
+
+/// Returns the value of a key whose parts are separated by dots
+pub fn get<'a>(userdata: &'a HashMap<String, toml::Value>, key: &str) -> Option<&'a toml::Value> {
+    let mut parts = key.split('.');
+    let mut value = userdata.get(parts.next()?)?;
+
+    for part in parts {
+        value = value.as_table()?.get(part)?;
+    }
+
+    Some(value)
+}
+
+/// Sets the value of a key whose parts are separated by dots
+/// and creates the tables leading up to it.
+/// Fails if one of the parents is set to a value that isn't a table
+pub fn insert(
+    userdata: &mut HashMap<String, toml::Value>,
+    key: &str,
+    value: toml::Value,
+) -> std::result::Result<(), SchemaProblem> {
+    match key.split_once('.') {
+        Some((first, rest)) => insert_nested(
+            userdata
+                .entry(first.to_owned())
+                .or_insert_with(|| toml::Value::Table(Default::default())),
+            key,
+            rest,
+            value,
+        ),
+        None => {
+            userdata.insert(key.to_owned(), value);
+            Ok(())
+        }
+    }
+}
+
+/// Sets the remaining parts `rest` of the `key` in the target table
+fn insert_nested(
+    target: &mut toml::Value,
+    key: &str,
+    rest: &str,
+    value: toml::Value,
+) -> std::result::Result<(), SchemaProblem> {
+    let Some(table) = target.as_table_mut() else {
+        let parent = key.strip_suffix(rest).unwrap_or(key).trim_end_matches('.');
+
+        return Err(SchemaProblem::NotATable {
+            key: key.to_owned(),
+            parent: parent.to_owned(),
+        });
+    };
+
+    match rest.split_once('.') {
+        Some((first, rest)) => insert_nested(
+            table
+                .entry(first)
+                .or_insert_with(|| toml::Value::Table(Default::default())),
+            key,
+            rest,
+            value,
+        ),
+        None => {
+            table.insert(rest.to_owned(), value);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{get, insert, SchemaProblem};
+
+    #[test]
+    fn it_inserts_nested_keys() {
+        let mut userdata = HashMap::new();
+        insert(&mut userdata, "a.b.c", toml::Value::Integer(1)).unwrap();
+
+        assert_eq!(get(&userdata, "a.b.c"), Some(&toml::Value::Integer(1)));
+    }
+
+    #[test]
+    fn it_refuses_to_replace_values_that_are_not_tables() {
+        let mut userdata = HashMap::from([(String::from("a"), toml::Value::Integer(1))]);
+        let problem = insert(&mut userdata, "a.b.c", toml::Value::Integer(2)).unwrap_err();
+
+        assert!(matches!(problem, SchemaProblem::NotATable { parent, .. } if parent == "a"));
+        assert_eq!(userdata["a"], toml::Value::Integer(1));
+    }
+}
@@ -1,42 +1,319 @@
 use std::{
-    fs::{self, File},
-    io::Write,
+    fs,
+    io::{self, IsTerminal, Write},
     mem,
     path::{Path, PathBuf},
     process::Command,
 };
 
-use chksum::sha2_256::chksum;
 use dialoguer::Confirm;
 
-use miette::{Context, IntoDiagnostic, Result};
+use miette::{bail, Context, IntoDiagnostic, Result};
 
 use tempfile::NamedTempFile;
 
-use crate::repo::hooks::{ApplyAllContext, ApplyEachContext, Hooks};
+use crate::{
+    backup::BackupRun,
+    config::{ApplyPolicy, SiloConfig},
+    repo::hooks::{ApplyAllContext, ApplyEachContext, Hooks},
+    state::{checksum, AppliedState},
+};
 
 use super::FsAccess;
 
 pub struct BufferedFsAccess {
     repo: PathBuf,
-    mappings: Vec<(NamedTempFile, PathBuf)>,
+    mappings: Vec<Mapping>,
     diff_tool: String,
+    merge_tool: Option<String>,
+    backup: Option<BackupRun>,
+    backup_retention: usize,
+    policy: ApplyPolicy,
     hooks: Hooks,
 }
 
+struct Mapping {
+    kind: MappingKind,
+    src: PathBuf,
+    dst: PathBuf,
+}
+
+enum MappingKind {
+    /// The contents to write are stored in the temporary file
+    File(NamedTempFile),
+    /// The destination is linked to the source
+    Symlink,
+}
+
 impl BufferedFsAccess {
-    pub fn new(repo: PathBuf, diff_tool: String, hooks: Hooks) -> Self {
-        Self {
+    pub fn new(repo: PathBuf, config: &SiloConfig, hooks: Hooks) -> Result<Self> {
+        let backup = if config.backup {
+            Some(BackupRun::new()?)
+        } else {
+            None
+        };
+
+        Ok(Self {
             mappings: Vec::new(),
             repo,
-            diff_tool,
+            diff_tool: config.diff_tool.to_owned(),
+            merge_tool: config.merge_tool.to_owned(),
+            backup,
+            backup_retention: config.backup_retention,
+            policy: config.apply_policy,
             hooks,
+        })
+    }
+
+    fn write_mapping(&mut self, mapping: &Mapping, state: &mut AppliedState) -> Result<()> {
+        match &mapping.kind {
+            MappingKind::File(tmp) => self.write_file(tmp, &mapping.src, &mapping.dst, state),
+            MappingKind::Symlink => self.write_symlink(&mapping.src, &mapping.dst, state),
+        }
+    }
+
+    fn write_file(
+        &mut self,
+        tmp: &NamedTempFile,
+        src: &Path,
+        dst: &Path,
+        state: &mut AppliedState,
+    ) -> Result<()> {
+        let base = state.base(dst);
+        let decision = self.confirm_write(base.as_deref(), tmp.path(), dst)?;
+        let write_src = match &decision {
+            WriteDecision::Write => tmp.path(),
+            WriteDecision::Merged(merged) => merged.path(),
+            WriteDecision::Skip => {
+                log::info!("Skipping {dst:?}");
+                return Ok(());
+            }
+        };
+        ensure_parent(dst.parent().unwrap())?;
+
+        if let Some(backup) = self.backup.as_mut() {
+            if dst.exists() && checksum(dst)? != checksum(write_src)? {
+                backup.backup(dst)?;
+            }
+        }
+
+        if dst.is_symlink() {
+            log::info!("Replacing symlink {dst:?} with a file");
+            fs::remove_file(dst)
+                .into_diagnostic()
+                .with_context(|| format!("removing symlink {dst:?}"))?;
+        }
+
+        self.hooks.before_apply_each(ApplyEachContext {
+            repo: self.repo.clone(),
+            src: write_src.to_owned(),
+            dst: dst.to_owned(),
+        })?;
+
+        fs::copy(write_src, dst)
+            .into_diagnostic()
+            .with_context(|| format!("copying {write_src:?} to {dst:?}"))?;
+
+        self.hooks.after_apply_each(ApplyEachContext {
+            repo: self.repo.clone(),
+            src: write_src.to_owned(),
+            dst: dst.to_owned(),
+        })?;
+        // the rendered contents are recorded even when merged
+        // so that the next apply only sees the changes to the repo
+        state.record(src, dst, tmp.path())?;
+        log::info!("Updated {dst:?}");
+
+        Ok(())
+    }
+
+    fn write_symlink(&mut self, src: &Path, dst: &Path, state: &mut AppliedState) -> Result<()> {
+        if fs::read_link(dst).is_ok_and(|t| t == src) {
+            log::debug!("{dst:?} is already linked to {src:?}");
+            return state.record(src, dst, src);
+        }
+
+        if dst.exists() || dst.is_symlink() {
+            if !self.confirm_replace(src, dst)? {
+                log::info!("Skipping {dst:?}");
+                return Ok(());
+            }
+            if let Some(backup) = self.backup.as_mut() {
+                if dst.is_file() && !dst.is_symlink() && checksum(dst)? != checksum(src)? {
+                    backup.backup(dst)?;
+                }
+            }
+            fs::remove_file(dst)
+                .into_diagnostic()
+                .with_context(|| format!("removing {dst:?}"))?;
+        }
+        ensure_parent(dst.parent().unwrap())?;
+
+        self.hooks.before_apply_each(ApplyEachContext {
+            repo: self.repo.clone(),
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+        })?;
+
+        create_symlink(src, dst)
+            .into_diagnostic()
+            .with_context(|| format!("linking {dst:?} to {src:?}"))?;
+
+        self.hooks.after_apply_each(ApplyEachContext {
+            repo: self.repo.clone(),
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+        })?;
+        state.record(src, dst, src)?;
+        log::info!("Linked {dst:?}");
+
+        Ok(())
+    }
+
+    /// Asks whether an existing file should be replaced with a link to `src`
+    fn confirm_replace(&self, src: &Path, dst: &Path) -> Result<bool> {
+        if dst.is_file() && checksum(dst)? == checksum(src)? {
+            return Ok(true);
+        }
+        match self.policy {
+            ApplyPolicy::Overwrite => Ok(true),
+            ApplyPolicy::OnlyNew | ApplyPolicy::NoOverwrite => Ok(false),
+            ApplyPolicy::Prompt => {
+                if dst.is_file() {
+                    show_diff(&self.diff_tool, dst, src)?;
+                }
+                confirm("Do you want to replace the existing file with a link into the repo?")
+            }
+        }
+    }
+
+    fn confirm_write(&self, base: Option<&Path>, new: &Path, old: &Path) -> Result<WriteDecision> {
+        if !old.exists() {
+            return Ok(WriteDecision::Write);
+        }
+        let new_sum = checksum(new)?;
+        let old_sum = checksum(old)?;
+
+        if new_sum == old_sum {
+            return Ok(WriteDecision::Write);
+        }
+        match self.policy {
+            ApplyPolicy::Overwrite => return Ok(WriteDecision::Write),
+            ApplyPolicy::OnlyNew => return Ok(WriteDecision::Skip),
+            ApplyPolicy::Prompt | ApplyPolicy::NoOverwrite => {}
+        }
+        let keep_local = self.policy == ApplyPolicy::NoOverwrite;
+        let prompt = match base.map(checksum).transpose()? {
+            Some(base_sum) if base_sum == old_sum => {
+                if keep_local {
+                    return Ok(WriteDecision::Write);
+                }
+                "Do you want to apply these changes?"
+            }
+            Some(base_sum) if base_sum == new_sum => {
+                log::info!("{old:?} was changed locally");
+                if keep_local {
+                    return Ok(WriteDecision::Skip);
+                }
+                "Do you want to overwrite your local changes?"
+            }
+            Some(_) => {
+                log::warn!("{old:?} was changed locally and in the repo");
+                if keep_local {
+                    return Ok(WriteDecision::Skip);
+                }
+                return self.merge(base.unwrap(), new, old);
+            }
+            None => {
+                if keep_local {
+                    return Ok(WriteDecision::Skip);
+                }
+                "Do you want to apply these changes?"
+            }
+        };
+
+        show_diff(&self.diff_tool, old, new)?;
+
+        if confirm(prompt)? {
+            Ok(WriteDecision::Write)
+        } else {
+            Ok(WriteDecision::Skip)
+        }
+    }
+
+    /// Merges the local changes in `old` with the new contents using
+    /// `base` as the common ancestor
+    fn merge(&self, base: &Path, new: &Path, old: &Path) -> Result<WriteDecision> {
+        let read = |path: &Path| {
+            fs::read_to_string(path)
+                .into_diagnostic()
+                .with_context(|| format!("reading file {path:?}"))
+        };
+        let (Ok(base_contents), Ok(new_contents), Ok(old_contents)) =
+            (read(base), read(new), read(old))
+        else {
+            log::warn!("Cannot merge binary file {old:?}");
+            show_diff(&self.diff_tool, old, new)?;
+
+            return if confirm("Do you want to overwrite your local changes?")? {
+                Ok(WriteDecision::Write)
+            } else {
+                Ok(WriteDecision::Skip)
+            };
+        };
+        let (merged_contents, conflicts) =
+            match diffy::merge(&base_contents, &old_contents, &new_contents) {
+                Ok(merged) => (merged, false),
+                Err(conflicted) => (conflicted, true),
+            };
+        let mut merged = tmpfile()?;
+        merged
+            .write_all(merged_contents.as_bytes())
+            .into_diagnostic()
+            .with_context(|| format!("writing merge result {:?}", merged.path()))?;
+        fs::set_permissions(
+            merged.path(),
+            fs::metadata(new).into_diagnostic()?.permissions(),
+        )
+        .into_diagnostic()?;
+
+        let prompt = if let Some(merge_tool) = &self.merge_tool {
+            let status = Command::new(merge_tool)
+                .arg(base)
+                .arg(old)
+                .arg(new)
+                .arg(merged.path())
+                .spawn()
+                .into_diagnostic()
+                .context("spawn merge tool")?
+                .wait()
+                .into_diagnostic()
+                .context("wait for merge tool to exit")?;
+
+            if !status.success() {
+                log::warn!("Merge tool exited with {status}");
+                return Ok(WriteDecision::Skip);
+            }
+            "Do you want to apply the merged result?"
+        } else if conflicts {
+            log::warn!("The changes to {old:?} conflict");
+            "Do you want to apply the merged result with conflict markers?"
+        } else {
+            "Do you want to apply the merged result?"
+        };
+
+        show_diff(&self.diff_tool, old, merged.path())?;
+
+        if confirm(prompt)? {
+            Ok(WriteDecision::Merged(merged))
+        } else {
+            Ok(WriteDecision::Skip)
         }
     }
 }
 
 impl FsAccess for BufferedFsAccess {
-    fn write_all(&mut self, dst: &std::path::Path, buf: &[u8]) -> miette::Result<()> {
+    fn write_all(&mut self, src: &Path, dst: &Path, buf: &[u8]) -> miette::Result<()> {
         let mut tmp = tmpfile()?;
         tmp.write_all(buf).into_diagnostic().with_context(|| {
             format!(
@@ -45,26 +322,47 @@ impl FsAccess for BufferedFsAccess {
                 tmp.path()
             )
         })?;
-        self.mappings.push((tmp, dst.to_owned()));
+        self.mappings.push(Mapping {
+            kind: MappingKind::File(tmp),
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+        });
 
         Ok(())
     }
 
-    fn copy(&mut self, src: &std::path::Path, dst: &std::path::Path) -> miette::Result<()> {
+    fn copy(&mut self, src: &Path, dst: &Path) -> miette::Result<()> {
         let tmp = tmpfile()?;
         fs::copy(src, tmp.path())
             .into_diagnostic()
             .with_context(|| format!("copying {src:?} to {:?}", tmp.path()))?;
-        self.mappings.push((tmp, dst.to_owned()));
+        self.mappings.push(Mapping {
+            kind: MappingKind::File(tmp),
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    fn symlink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.mappings.push(Mapping {
+            kind: MappingKind::Symlink,
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+        });
 
         Ok(())
     }
 
     fn set_permissions(&mut self, path: &Path, perm: fs::Permissions) -> Result<()> {
-        let found_entry = self.mappings.iter().find(|(_, p)| p == path);
+        let found_tmp = self.mappings.iter().find_map(|m| match &m.kind {
+            MappingKind::File(tmp) if m.dst == path => Some(tmp),
+            _ => None,
+        });
 
-        if let Some(entry) = found_entry {
-            fs::set_permissions(entry.0.path(), perm.clone())
+        if let Some(tmp) = found_tmp {
+            fs::set_permissions(tmp.path(), perm.clone())
                 .into_diagnostic()
                 .with_context(|| format!("Failed to set permissions {perm:?} on {path:?}"))?;
         }
@@ -74,40 +372,28 @@ impl FsAccess for BufferedFsAccess {
 
     fn persist(&mut self) -> Result<()> {
         let mappings = mem::take(&mut self.mappings);
-        let mut drop_list = Vec::new();
-        let paths: Vec<_> = mappings.iter().map(|(_, p)| p.to_owned()).collect();
+        let paths: Vec<_> = mappings.iter().map(|m| m.dst.to_owned()).collect();
+        let mut state = AppliedState::load()?;
 
         self.hooks.before_apply_all(ApplyAllContext {
             repo: self.repo.clone(),
             paths: paths.clone(),
         })?;
 
-        for (tmp, dst) in mappings {
-            if confirm_write(&self.diff_tool, tmp.path(), &dst)? {
-                ensure_parent(dst.parent().unwrap())?;
-
-                self.hooks.before_apply_each(ApplyEachContext {
-                    repo: self.repo.clone(),
-                    src: tmp.path().to_owned(),
-                    dst: dst.clone(),
-                })?;
-
-                fs::copy(tmp.path(), &dst)
-                    .into_diagnostic()
-                    .with_context(|| format!("copying {:?} to {dst:?}", tmp.path()))?;
-
-                self.hooks.after_apply_each(ApplyEachContext {
-                    repo: self.repo.clone(),
-                    src: tmp.path().to_owned(),
-                    dst: dst.clone(),
-                })?;
-                log::info!("Updated {dst:?}");
-            } else {
-                log::info!("Skipping {dst:?}");
+        for mapping in &mappings {
+            if let Err(e) = self.write_mapping(mapping, &mut state) {
+                // keep the record of the files that have already been written
+                state.save()?;
+                return Err(e);
             }
-            drop_list.push(tmp);
         }
-        mem::drop(drop_list);
+        mem::drop(mappings);
+        state.save()?;
+
+        if let Some(backup) = self.backup.as_ref().filter(|b| !b.is_empty()) {
+            log::info!("Overwritten files were backed up in run {}", backup.id);
+            BackupRun::prune(self.backup_retention)?;
+        }
 
         self.hooks.after_apply_all(ApplyAllContext {
             repo: self.repo.clone(),
@@ -124,21 +410,35 @@ fn tmpfile() -> Result<NamedTempFile> {
         .context("failed to create tmp file")
 }
 
-fn confirm_write(diff_tool: &str, new: &Path, old: &Path) -> Result<bool> {
-    if !old.exists() {
-        return Ok(true);
-    }
-    let f1 = File::open(new)
-        .into_diagnostic()
-        .with_context(|| format!("opening file {new:?}"))?;
-    let f2 = File::open(old)
-        .into_diagnostic()
-        .with_context(|| format!("opening file {old:?}"))?;
+enum WriteDecision {
+    /// Write the new contents
+    Write,
+    /// Write the result of merging the new contents with local changes
+    Merged(NamedTempFile),
+    /// Keep the existing file
+    Skip,
+}
 
-    if chksum(&f1).into_diagnostic()?.as_bytes() == chksum(&f2).into_diagnostic()?.as_bytes() {
-        return Ok(true);
+/// Fails if stdin is not a terminal, so that no prompt can be shown
+pub fn ensure_interactive() -> Result<()> {
+    if !io::stdin().is_terminal() {
+        bail!(
+            help = "use `--yes`, `--no-overwrite` or `--only-new` to apply without prompting",
+            "Cannot ask for confirmation because stdin is not a terminal"
+        );
     }
 
+    Ok(())
+}
+
+fn confirm(prompt: &str) -> Result<bool> {
+    Confirm::new()
+        .with_prompt(prompt)
+        .interact()
+        .into_diagnostic()
+}
+
+fn show_diff(diff_tool: &str, old: &Path, new: &Path) -> Result<()> {
     Command::new(diff_tool)
         .arg(old)
         .arg(new)
@@ -150,10 +450,17 @@ fn confirm_write(diff_tool: &str, new: &Path, old: &Path) -> Result<bool> {
         .context("wait for diff tool to exit")?;
     println!();
 
-    Confirm::new()
-        .with_prompt("Do you want to apply these changes?")
-        .interact()
-        .into_diagnostic()
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(src, dst)
+}
+
+#[cfg(windows)]
+fn create_symlink(src: &Path, dst: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(src, dst)
 }
 
 fn ensure_parent(parent: &Path) -> Result<(), miette::ErrReport> {
@@ -0,0 +1,76 @@
+use std::{
+    fs::{self, Permissions},
+    mem,
+    path::{Path, PathBuf},
+};
+
+use miette::{Context, IntoDiagnostic, Result};
+
+use super::FsAccess;
+
+/// Collects the rendered contents of all files without writing them
+#[derive(Default)]
+pub struct CollectFsAccess {
+    files: Vec<CollectedFile>,
+}
+
+pub struct CollectedFile {
+    pub src: PathBuf,
+    pub dst: PathBuf,
+    pub contents: Vec<u8>,
+    /// Whether the contents were rendered from a template
+    pub rendered: bool,
+    /// Whether dst is deployed as a symlink to src
+    pub link: bool,
+}
+
+impl CollectFsAccess {
+    pub fn take_files(&mut self) -> Vec<CollectedFile> {
+        mem::take(&mut self.files)
+    }
+
+    fn collect_plain(&mut self, src: &Path, dst: &Path, link: bool) -> Result<()> {
+        let contents = fs::read(src)
+            .into_diagnostic()
+            .with_context(|| format!("reading file {src:?}"))?;
+        self.files.push(CollectedFile {
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+            contents,
+            rendered: false,
+            link,
+        });
+
+        Ok(())
+    }
+}
+
+impl FsAccess for CollectFsAccess {
+    fn write_all(&mut self, src: &Path, dst: &Path, buf: &[u8]) -> Result<()> {
+        self.files.push(CollectedFile {
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+            contents: buf.to_vec(),
+            rendered: true,
+            link: false,
+        });
+
+        Ok(())
+    }
+
+    fn copy(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.collect_plain(src, dst, false)
+    }
+
+    fn symlink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.collect_plain(src, dst, true)
+    }
+
+    fn set_permissions(&mut self, _path: &Path, _perm: Permissions) -> Result<()> {
+        Ok(())
+    }
+
+    fn persist(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use std::{
+    fs::{self, Permissions},
+    io::Write,
+    mem,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use diffy::DiffOptions;
+use miette::{Context, IntoDiagnostic, Result};
+use tempfile::NamedTempFile;
+
+use super::FsAccess;
+
+/// Collects all writes and prints the differences to the
+/// current destination files without changing them
+pub struct DiffFsAccess {
+    entries: Vec<DiffEntry>,
+    diff_tool: Option<String>,
+    changed: usize,
+}
+
+enum DiffEntry {
+    File { dst: PathBuf, contents: Vec<u8> },
+    Link { target: PathBuf, dst: PathBuf },
+}
+
+impl DiffFsAccess {
+    /// Creates a new diff access. If a `diff_tool` is passed it
+    /// will be called for every changed file instead of printing a unified diff
+    pub fn new(diff_tool: Option<String>) -> Self {
+        Self {
+            entries: Vec::new(),
+            diff_tool,
+            changed: 0,
+        }
+    }
+
+    /// Returns the number of files that would change
+    pub fn changed(&self) -> usize {
+        self.changed
+    }
+
+    /// Prints where dst would link to if it isn't linked to target yet
+    fn print_link_change(&mut self, target: &Path, dst: &Path) {
+        let current = match fs::read_link(dst) {
+            Ok(current) if current == target => return,
+            Ok(current) => format!("a link to {}", current.display()),
+            Err(_) if dst.exists() => String::from("a regular file"),
+            Err(_) => String::from("missing"),
+        };
+        self.changed += 1;
+        println!(
+            "{} -> {} (currently {current})",
+            dst.display(),
+            target.display()
+        );
+    }
+
+    fn run_diff_tool(&self, diff_tool: &str, dst: &Path, new: &[u8]) -> Result<()> {
+        let mut tmp = NamedTempFile::new()
+            .into_diagnostic()
+            .context("failed to create tmp file")?;
+        tmp.write_all(new)
+            .into_diagnostic()
+            .with_context(|| format!("writing file contents {:?}", tmp.path()))?;
+        let empty;
+        let old = if dst.exists() {
+            dst
+        } else {
+            empty = NamedTempFile::new()
+                .into_diagnostic()
+                .context("failed to create tmp file")?;
+            empty.path()
+        };
+
+        Command::new(diff_tool)
+            .arg(old)
+            .arg(tmp.path())
+            .spawn()
+            .into_diagnostic()
+            .context("spawn diff tool")?
+            .wait()
+            .into_diagnostic()
+            .context("wait for diff tool to exit")?;
+
+        Ok(())
+    }
+}
+
+/// Prints a unified diff between two versions of a file
+pub fn print_unified_diff(path: &Path, old: &[u8], new: &[u8]) {
+    let old = String::from_utf8_lossy(old);
+    let new = String::from_utf8_lossy(new);
+    let path = path.to_string_lossy().into_owned();
+
+    print!(
+        "{}",
+        DiffOptions::new()
+            .set_original_filename(path.clone())
+            .set_modified_filename(path)
+            .create_patch(&old, &new)
+    );
+}
+
+impl FsAccess for DiffFsAccess {
+    fn write_all(&mut self, _src: &Path, dst: &Path, buf: &[u8]) -> Result<()> {
+        self.entries.push(DiffEntry::File {
+            dst: dst.to_owned(),
+            contents: buf.to_vec(),
+        });
+
+        Ok(())
+    }
+
+    fn copy(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        let contents = fs::read(src)
+            .into_diagnostic()
+            .with_context(|| format!("reading file {src:?}"))?;
+        self.entries.push(DiffEntry::File {
+            dst: dst.to_owned(),
+            contents,
+        });
+
+        Ok(())
+    }
+
+    fn symlink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.entries.push(DiffEntry::Link {
+            target: src.to_owned(),
+            dst: dst.to_owned(),
+        });
+
+        Ok(())
+    }
+
+    fn set_permissions(&mut self, _path: &Path, _perm: Permissions) -> Result<()> {
+        Ok(())
+    }
+
+    fn persist(&mut self) -> Result<()> {
+        let entries = mem::take(&mut self.entries);
+
+        for entry in entries {
+            let (dst, new) = match entry {
+                DiffEntry::File { dst, contents } => (dst, contents),
+                DiffEntry::Link { target, dst } => {
+                    self.print_link_change(&target, &dst);
+                    continue;
+                }
+            };
+            let old = if dst.exists() {
+                fs::read(&dst)
+                    .into_diagnostic()
+                    .with_context(|| format!("reading file {dst:?}"))?
+            } else {
+                Vec::new()
+            };
+            if dst.exists() && old == new {
+                if dst.is_symlink() {
+                    self.changed += 1;
+                    println!("{} would replace the link with a copy", dst.display());
+                }
+                continue;
+            }
+            self.changed += 1;
+
+            if let Some(diff_tool) = &self.diff_tool {
+                println!("{}", dst.display());
+                self.run_diff_tool(diff_tool, &dst, &new)?;
+                println!();
+            } else {
+                print_unified_diff(&dst, &old, &new);
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,161 @@
+use std::{
+    fs::{self, Permissions},
+    mem,
+    path::{Path, PathBuf},
+};
+
+use miette::{Context, IntoDiagnostic, Result};
+
+use super::FsAccess;
+
+/// Records all file operations and prints the resulting plan
+/// instead of writing anything to the destination
+#[derive(Default)]
+pub struct DryRunFsAccess {
+    entries: Vec<PlannedWrite>,
+}
+
+struct PlannedWrite {
+    dst: PathBuf,
+    contents: Vec<u8>,
+    permissions: Option<Permissions>,
+    /// The target if the file is deployed as a symlink
+    link: Option<PathBuf>,
+}
+
+enum Change {
+    Create,
+    Modify,
+    Unchanged,
+}
+
+impl FsAccess for DryRunFsAccess {
+    fn write_all(&mut self, _src: &Path, dst: &Path, buf: &[u8]) -> Result<()> {
+        self.entries.push(PlannedWrite {
+            dst: dst.to_owned(),
+            contents: buf.to_vec(),
+            permissions: None,
+            link: None,
+        });
+
+        Ok(())
+    }
+
+    fn copy(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        let contents = fs::read(src)
+            .into_diagnostic()
+            .with_context(|| format!("reading file {src:?}"))?;
+        let permissions = fs::metadata(src)
+            .into_diagnostic()
+            .with_context(|| format!("reading metadata of {src:?}"))?
+            .permissions();
+        self.entries.push(PlannedWrite {
+            dst: dst.to_owned(),
+            contents,
+            permissions: Some(permissions),
+            link: None,
+        });
+
+        Ok(())
+    }
+
+    fn symlink(&mut self, src: &Path, dst: &Path) -> Result<()> {
+        self.entries.push(PlannedWrite {
+            dst: dst.to_owned(),
+            contents: Vec::new(),
+            permissions: None,
+            link: Some(src.to_owned()),
+        });
+
+        Ok(())
+    }
+
+    fn set_permissions(&mut self, path: &Path, perm: Permissions) -> Result<()> {
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.dst == path) {
+            entry.permissions = Some(perm);
+        }
+
+        Ok(())
+    }
+
+    fn persist(&mut self) -> Result<()> {
+        let entries = mem::take(&mut self.entries);
+        let (mut created, mut modified, mut unchanged) = (0, 0, 0);
+
+        for entry in entries {
+            let change = if let Some(target) = &entry.link {
+                if fs::read_link(&entry.dst).is_ok_and(|t| &t == target) {
+                    Change::Unchanged
+                } else if entry.dst.exists() || entry.dst.is_symlink() {
+                    Change::Modify
+                } else {
+                    Change::Create
+                }
+            } else if entry.dst.exists() {
+                let old = fs::read(&entry.dst)
+                    .into_diagnostic()
+                    .with_context(|| format!("reading file {:?}", entry.dst))?;
+                if old == entry.contents {
+                    Change::Unchanged
+                } else {
+                    Change::Modify
+                }
+            } else {
+                Change::Create
+            };
+
+            let description = match &entry.link {
+                Some(target) => format!("{} -> {}", entry.dst.display(), target.display()),
+                None => entry.dst.display().to_string(),
+            };
+
+            match change {
+                Change::Create => {
+                    created += 1;
+                    println!("create    {description}");
+                }
+                Change::Modify => {
+                    modified += 1;
+                    println!("modify    {description}");
+                }
+                Change::Unchanged => {
+                    unchanged += 1;
+                    println!("unchanged {description}");
+                }
+            }
+
+            if let Some(new_perm) = entry.permissions {
+                let old_perm = fs::metadata(&entry.dst).ok().map(|m| m.permissions());
+
+                match old_perm {
+                    Some(old_perm) if old_perm != new_perm => println!(
+                        "          permissions {} -> {}",
+                        fmt_permissions(&old_perm),
+                        fmt_permissions(&new_perm)
+                    ),
+                    None => println!("          permissions {}", fmt_permissions(&new_perm)),
+                    _ => {}
+                }
+            }
+        }
+        println!();
+        println!("{created} to create, {modified} to modify, {unchanged} unchanged");
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn fmt_permissions(perm: &Permissions) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    format!("{:o}", perm.mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn fmt_permissions(perm: &Permissions) -> String {
+    if perm.readonly() {
+        String::from("readonly")
+    } else {
+        String::from("writable")
+    }
+}
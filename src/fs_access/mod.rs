@@ -2,15 +2,24 @@ use miette::Result;
 use std::{fs::Permissions, path::Path};
 
 mod buffered;
-pub use buffered::BufferedFsAccess;
+mod collect;
+mod diff;
+mod dry_run;
+pub use buffered::{ensure_interactive, BufferedFsAccess};
+pub use collect::CollectFsAccess;
+pub use diff::{print_unified_diff, DiffFsAccess};
+pub use dry_run::DryRunFsAccess;
 
 pub trait FsAccess {
-    /// Write all bytes to dst
-    fn write_all(&mut self, dst: &Path, buf: &[u8]) -> Result<()>;
+    /// Write all bytes rendered from src to dst
+    fn write_all(&mut self, src: &Path, dst: &Path, buf: &[u8]) -> Result<()>;
 
     /// Copy src to dst
     fn copy(&mut self, src: &Path, dst: &Path) -> Result<()>;
 
+    /// Create a symlink at dst pointing to src
+    fn symlink(&mut self, src: &Path, dst: &Path) -> Result<()>;
+
     /// Sets permissions on a file
     fn set_permissions(&mut self, path: &Path, perm: Permissions) -> Result<()>;
 
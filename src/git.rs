@@ -0,0 +1,872 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::AtomicBool,
+};
+
+use gix::{
+    bstr::{BString, ByteSlice},
+    config::tree::gitoxide::{Author, Committer},
+    objs::{
+        tree::{self, EntryKind, EntryMode},
+        Tree,
+    },
+    progress::Discard,
+    refs::{transaction::PreviousValue, FullName},
+    remote::Direction,
+    worktree::stack::state::ignore::Source,
+    AttributeStack, ObjectId, Repository,
+};
+use miette::{bail, miette, IntoDiagnostic, Result};
+
+use crate::utils::Describe;
+
+type TreeEntries = HashMap<BString, (EntryMode, ObjectId)>;
+
+/// The state of the repo compared to its last commit and its remote
+pub struct RepoStatus {
+    pub branch: Option<String>,
+    pub upstream: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+    pub changes: Vec<(Change, PathBuf)>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Change {
+    New,
+    Modified,
+    Deleted,
+}
+
+impl Change {
+    pub fn label(&self) -> &'static str {
+        match self {
+            Change::New => "new",
+            Change::Modified => "modified",
+            Change::Deleted => "deleted",
+        }
+    }
+}
+
+impl RepoStatus {
+    /// Returns warnings about uncommitted changes and diverging from the remote
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        if !self.changes.is_empty() {
+            warnings.push(format!(
+                "The repo has {} uncommitted change(s). Run `silo commit` to record them",
+                self.changes.len()
+            ));
+        }
+        if let Some(upstream) = &self.upstream {
+            if self.behind > 0 {
+                warnings.push(format!(
+                    "The repo is {} commit(s) behind {upstream}. Run `silo update` to pull them",
+                    self.behind
+                ));
+            }
+            if self.ahead > 0 {
+                warnings.push(format!(
+                    "The repo is {} commit(s) ahead of {upstream}. Run `silo push` to push them",
+                    self.ahead
+                ));
+            }
+        }
+
+        warnings
+    }
+}
+
+impl fmt::Display for RepoStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.branch {
+            Some(branch) => write!(f, "On branch {branch}")?,
+            None => write!(f, "HEAD is detached")?,
+        }
+        match &self.upstream {
+            Some(upstream) => writeln!(
+                f,
+                " tracking {upstream} ({} ahead, {} behind)",
+                self.ahead, self.behind
+            )?,
+            None => writeln!(f, " without upstream")?,
+        }
+        if self.changes.is_empty() {
+            writeln!(f, "No uncommitted changes")?;
+        }
+        for (change, path) in &self.changes {
+            writeln!(f, "{:<15}{}", change.label(), path.display())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the current branch, the number of commits it differs from its
+/// upstream and all files that changed since the last commit.
+/// The remote isn't fetched so the counts are relative to the last fetch
+pub fn status(path: &Path) -> Result<RepoStatus> {
+    repo_status(&open(path)?)
+}
+
+fn repo_status(repo: &Repository) -> Result<RepoStatus> {
+    let head_name = repo.head_name().describe("reading HEAD")?;
+    let head_id = repo.head_id().ok().map(|id| id.detach());
+    let mut status = RepoStatus {
+        branch: head_name.as_ref().map(|n| n.shorten().to_string()),
+        upstream: None,
+        ahead: 0,
+        behind: 0,
+        changes: Vec::new(),
+    };
+
+    if let (Some(head_name), Some(head_id)) = (&head_name, head_id) {
+        if let Some((upstream, upstream_id)) = find_upstream(repo, head_name)? {
+            (status.ahead, status.behind) = ahead_behind(repo, head_id, upstream_id)?;
+            status.upstream = Some(upstream.shorten().to_string());
+        }
+    }
+
+    let committed = match head_id {
+        Some(id) => tree_entries(repo, id)?,
+        None => TreeEntries::new(),
+    };
+    let workdir = repo
+        .work_dir()
+        .ok_or_else(|| miette!("The repository has no worktree"))?;
+
+    for (path, (_, id)) in &committed {
+        let file = gix::path::from_bstr(path.as_bstr()).into_owned();
+
+        match worktree_id(repo, &workdir.join(&file))? {
+            None => status.changes.push((Change::Deleted, file)),
+            Some(current) if current != *id => status.changes.push((Change::Modified, file)),
+            _ => {}
+        }
+    }
+
+    let index = repo.index_or_empty().describe("reading index")?;
+    let mut excludes = repo
+        .excludes(&index, None, Source::WorktreeThenIdMappingIfNotSkipped)
+        .describe("reading ignore files")?;
+    collect_untracked(
+        workdir,
+        Path::new(""),
+        &committed,
+        &mut excludes,
+        &mut status.changes,
+    )?;
+    status.changes.sort_by(|a, b| a.1.cmp(&b.1));
+
+    Ok(status)
+}
+
+/// Returns the upstream reference of the branch and the commit it points to
+/// if the branch or the repo has a remote that was fetched before
+fn find_upstream(repo: &Repository, head_name: &FullName) -> Result<Option<(FullName, ObjectId)>> {
+    let remote_name = repo
+        .branch_remote_name(head_name.shorten(), Direction::Fetch)
+        .map(|n| n.as_bstr().to_string())
+        .or_else(|| {
+            repo.find_default_remote(Direction::Fetch)
+                .and_then(|r| r.ok())
+                .and_then(|r| r.name().map(|n| n.as_bstr().to_string()))
+        });
+    let Some(remote_name) = remote_name else {
+        return Ok(None);
+    };
+    let upstream = upstream_ref(repo, head_name, &remote_name)?;
+    let Ok(mut reference) = repo.find_reference(upstream.as_ref()) else {
+        return Ok(None);
+    };
+    let upstream_id = reference
+        .peel_to_id_in_place()
+        .describe("resolving upstream reference")?
+        .detach();
+
+    Ok(Some((upstream, upstream_id)))
+}
+
+/// Counts the commits that are only reachable from `local` and only reachable
+/// from `remote`. The history is walked newest first and the walk stops as soon
+/// as only commits reachable from both are left, so the common history isn't read
+fn ahead_behind(repo: &Repository, local: ObjectId, remote: ObjectId) -> Result<(usize, usize)> {
+    const LOCAL: u8 = 1;
+    const REMOTE: u8 = 2;
+    const COMMON: u8 = LOCAL | REMOTE;
+
+    let mut flags = HashMap::from([(local, LOCAL)]);
+    *flags.entry(remote).or_default() |= REMOTE;
+    let mut queue = BinaryHeap::new();
+    queue.push((commit_time(repo, local)?, local));
+    queue.push((commit_time(repo, remote)?, remote));
+
+    while queue.iter().any(|(_, id)| flags[id] != COMMON) {
+        let Some((_, id)) = queue.pop() else {
+            break;
+        };
+        let flag = flags[&id];
+        let commit = repo
+            .find_object(id)
+            .describe("finding commit")?
+            .try_into_commit()
+            .describe("reading commit")?;
+
+        // commits are queued again when they are reached from the other side later,
+        // which happens if commit times are equal or out of order
+        for parent in commit.parent_ids() {
+            let parent = parent.detach();
+            let parent_flag = flags.entry(parent).or_default();
+
+            if *parent_flag | flag != *parent_flag {
+                *parent_flag |= flag;
+                queue.push((commit_time(repo, parent)?, parent));
+            }
+        }
+    }
+    let ahead = flags.values().filter(|f| **f == LOCAL).count();
+    let behind = flags.values().filter(|f| **f == REMOTE).count();
+
+    Ok((ahead, behind))
+}
+
+fn commit_time(repo: &Repository, id: ObjectId) -> Result<i64> {
+    repo.find_object(id)
+        .describe("finding commit")?
+        .try_into_commit()
+        .describe("reading commit")?
+        .time()
+        .map(|t| t.seconds)
+        .describe("reading commit time")
+}
+
+/// Recursively adds all files in the directory that are neither committed nor ignored
+fn collect_untracked(
+    workdir: &Path,
+    relative: &Path,
+    committed: &TreeEntries,
+    excludes: &mut AttributeStack<'_>,
+    changes: &mut Vec<(Change, PathBuf)>,
+) -> Result<()> {
+    let dir = workdir.join(relative);
+    let entries = fs::read_dir(&dir).with_describe(|| format!("reading directory {dir:?}"))?;
+
+    for entry in entries {
+        let entry = entry.with_describe(|| format!("reading directory {dir:?}"))?;
+        let path = relative.join(entry.file_name());
+        let is_dir = entry
+            .file_type()
+            .with_describe(|| format!("reading file type of {path:?}"))?
+            .is_dir();
+
+        if path == Path::new(".git") {
+            continue;
+        }
+        let excluded = excludes
+            .at_path(&path, Some(is_dir))
+            .with_describe(|| format!("checking if {path:?} is ignored"))?
+            .is_excluded();
+
+        if excluded {
+            continue;
+        }
+        if is_dir {
+            collect_untracked(workdir, &path, committed, excludes, changes)?;
+        } else if !committed.contains_key(
+            gix::path::to_unix_separators_on_windows(gix::path::into_bstr(&path)).as_ref(),
+        ) {
+            changes.push((Change::New, path));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fetches the default remote and fast-forwards the current branch
+/// to its upstream. Returns true if the worktree was changed
+pub fn update(path: &Path) -> Result<bool> {
+    let repo = open(path)?;
+    let remote = repo
+        .find_default_remote(Direction::Fetch)
+        .ok_or_else(|| miette!("The repository has no remote to fetch from"))?
+        .describe("finding default remote")?;
+    let remote_name = remote
+        .name()
+        .map(|n| n.as_bstr().to_string())
+        .unwrap_or_else(|| String::from("origin"));
+    log::info!("Fetching from {remote_name}");
+    let interrupt = AtomicBool::new(false);
+
+    remote
+        .connect(Direction::Fetch)
+        .describe("connecting to remote")?
+        .prepare_fetch(Discard, Default::default())
+        .describe("preparing fetch")?
+        .receive(Discard, &interrupt)
+        .describe("fetching from remote")?;
+
+    let head_name = repo
+        .head_name()
+        .describe("reading HEAD")?
+        .ok_or_else(|| miette!("HEAD is detached. Please check out a branch"))?;
+    let upstream = upstream_ref(&repo, &head_name, &remote_name)?;
+    let new_id = repo
+        .find_reference(upstream.as_ref())
+        .with_describe(|| format!("finding upstream reference {upstream}"))?
+        .peel_to_id_in_place()
+        .describe("resolving upstream reference")?
+        .detach();
+    let old_id = repo.head_id().ok().map(|id| id.detach());
+
+    if Some(new_id) == old_id {
+        log::info!("Already up to date");
+        return Ok(false);
+    }
+    if let Some(old_id) = old_id {
+        let is_ancestor = repo
+            .rev_walk([new_id])
+            .all()
+            .describe("walking upstream history")?
+            .filter_map(|info| info.ok())
+            .any(|info| info.id == old_id);
+
+        if !is_ancestor {
+            bail!(
+                help = "merge or rebase the changes manually with git",
+                "The branch {} has diverged from {upstream} and can't be fast-forwarded",
+                head_name.shorten()
+            );
+        }
+    }
+
+    let old_entries = match old_id {
+        Some(id) => tree_entries(&repo, id)?,
+        None => TreeEntries::new(),
+    };
+    if has_staged_changes(&repo, &old_entries)? {
+        bail!(
+            help = "commit or unstage the changes first",
+            "The index has staged changes that would be lost by the update"
+        );
+    }
+    let new_entries = tree_entries(&repo, new_id)?;
+    checkout(&repo, &old_entries, &new_entries)?;
+    write_index(&repo, new_id)?;
+
+    repo.reference(
+        head_name.clone(),
+        new_id,
+        PreviousValue::Any,
+        format!("silo: fast-forward to {upstream}"),
+    )
+    .describe("updating branch")?;
+    log::info!("Fast-forwarded {} to {new_id}", head_name.shorten());
+
+    Ok(true)
+}
+
+/// Stages all changes in the repo and commits them. If no message is given
+/// one listing the changed files is generated. Returns false if there was nothing to commit
+pub fn commit(path: &Path, message: Option<&str>) -> Result<bool> {
+    let repo = open(path)?;
+    let changes = repo_status(&repo)?.changes;
+
+    if changes.is_empty() {
+        log::info!("Nothing to commit");
+        return Ok(false);
+    }
+    let message = message
+        .map(String::from)
+        .unwrap_or_else(|| commit_message(&changes));
+    let workdir = repo
+        .work_dir()
+        .ok_or_else(|| miette!("The repository has no worktree"))?;
+    let head_id = repo.head_id().ok().map(|id| id.detach());
+    let mut entries = match head_id {
+        Some(id) => tree_entries(&repo, id)?,
+        None => TreeEntries::new(),
+    };
+
+    for (change, file) in &changes {
+        let key = gix::path::to_unix_separators_on_windows(gix::path::into_bstr(file)).into_owned();
+
+        if *change == Change::Deleted {
+            entries.remove(&key);
+        } else {
+            entries.insert(key, stage_file(&repo, &workdir.join(file))?);
+        }
+    }
+    let tree = write_tree(&repo, &entries)?;
+    let id = repo
+        .commit("HEAD", &message, tree, head_id)
+        .describe("creating commit")?
+        .detach();
+    write_index(&repo, id)?;
+    log::info!("Committed {} file(s)", changes.len());
+
+    Ok(true)
+}
+
+/// Pushes the current branch to its remote and sets the
+/// upstream if the branch isn't tracking a remote branch yet
+pub fn push(path: &Path) -> Result<()> {
+    let repo = open(path)?;
+    let head_name = repo
+        .head_name()
+        .describe("reading HEAD")?
+        .ok_or_else(|| miette!("HEAD is detached. Please check out a branch"))?;
+    let branch = head_name.shorten().to_string();
+
+    if repo
+        .branch_remote_name(branch.as_str(), Direction::Push)
+        .is_some()
+    {
+        run_git(path, &["push", "--quiet"])?;
+    } else {
+        let remote = repo
+            .find_default_remote(Direction::Push)
+            .ok_or_else(|| miette!("The repository has no remote to push to"))?
+            .describe("finding default remote")?;
+        let remote_name = remote
+            .name()
+            .map(|n| n.as_bstr().to_string())
+            .ok_or_else(|| miette!("The default remote has no name"))?;
+        run_git(
+            path,
+            &["push", "--quiet", "--set-upstream", &remote_name, &branch],
+        )?;
+    }
+    log::info!("Pushed {branch}");
+
+    Ok(())
+}
+
+/// Builds a commit message from the list of changed files
+fn commit_message(changes: &[(Change, PathBuf)]) -> String {
+    let lines: Vec<_> = changes
+        .iter()
+        .map(|(change, path)| {
+            let action = match change {
+                Change::New => "Add",
+                Change::Deleted => "Remove",
+                Change::Modified => "Update",
+            };
+            format!("{action} {}", path.display())
+        })
+        .collect();
+
+    match lines.as_slice() {
+        [line] => line.clone(),
+        _ => format!("Update {} files\n\n{}", lines.len(), lines.join("\n")),
+    }
+}
+
+/// Runs git in the repo with the given arguments and returns its stdout.
+/// Only pushing uses the git command line tool as gix can't push yet
+fn run_git(path: &Path, args: &[&str]) -> Result<String> {
+    let output = match process::Command::new("git")
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .output()
+    {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => bail!(
+            help = "install git and make sure it is in your `PATH`",
+            "The git command line tool is required to run `git {}`",
+            args[0]
+        ),
+        output => output.with_describe(|| format!("running git {}", args[0]))?,
+    };
+
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Opens the git repository and sets a fallback author and committer
+/// that are used for commits and reflog entries if none is configured
+fn open(path: &Path) -> Result<Repository> {
+    let mut repo = gix::open(path).with_describe(|| format!("opening git repository {path:?}"))?;
+
+    if repo.committer().is_none() || repo.author().is_none() {
+        let mut config = repo.config_snapshot_mut();
+
+        for (key, value) in [
+            (&Committer::NAME_FALLBACK, "silo"),
+            (&Committer::EMAIL_FALLBACK, "silo@localhost"),
+            (&Author::NAME_FALLBACK, "silo"),
+            (&Author::EMAIL_FALLBACK, "silo@localhost"),
+        ] {
+            config
+                .set_value(key, value)
+                .describe("setting fallback committer")?;
+        }
+        config.commit().describe("updating git config")?;
+    }
+
+    Ok(repo)
+}
+
+/// Returns the remote tracking reference of the given branch
+fn upstream_ref(repo: &Repository, head_name: &FullName, remote_name: &str) -> Result<FullName> {
+    if let Some(tracking) =
+        repo.branch_remote_tracking_ref_name(head_name.as_ref(), Direction::Fetch)
+    {
+        return Ok(tracking.describe("reading upstream branch")?.into_owned());
+    }
+    format!("refs/remotes/{remote_name}/{}", head_name.shorten())
+        .try_into()
+        .describe("building upstream reference name")
+}
+
+/// Returns all files in the tree of the given commit
+fn tree_entries(repo: &Repository, commit: ObjectId) -> Result<TreeEntries> {
+    let tree = repo
+        .find_object(commit)
+        .describe("finding commit")?
+        .peel_to_tree()
+        .describe("reading commit tree")?;
+    let entries = tree
+        .traverse()
+        .breadthfirst
+        .files()
+        .describe("traversing tree")?
+        .into_iter()
+        .filter(|e| e.mode.is_no_tree())
+        .map(|e| (e.filepath, (e.mode, e.oid)))
+        .collect();
+
+    Ok(entries)
+}
+
+/// Writes all files that changed between the old and new tree to the worktree.
+/// Fails without changing anything if a changed file was modified locally
+fn checkout(repo: &Repository, old: &TreeEntries, new: &TreeEntries) -> Result<()> {
+    let workdir = repo
+        .work_dir()
+        .ok_or_else(|| miette!("The repository has no worktree"))?;
+    let changed: BTreeSet<_> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|p| old.get(*p) != new.get(*p))
+        .collect();
+    let mut conflicts = Vec::new();
+
+    for path in &changed {
+        let file = workdir.join(gix::path::from_bstr(path.as_bstr()));
+        let current = worktree_id(repo, &file)?;
+        let old_id = old.get(*path).map(|(_, id)| *id);
+        let new_id = new.get(*path).map(|(_, id)| *id);
+
+        if current != old_id && current != new_id {
+            conflicts.push(file);
+        }
+    }
+    if !conflicts.is_empty() {
+        let files: Vec<_> = conflicts.iter().map(|p| format!("{p:?}")).collect();
+        bail!(
+            help = "commit or revert the local changes first",
+            "Local changes would be overwritten by the update: {}",
+            files.join(", ")
+        );
+    }
+
+    for path in changed {
+        let file = workdir.join(gix::path::from_bstr(path.as_bstr()));
+
+        if file.is_symlink() || file.is_file() {
+            fs::remove_file(&file).with_describe(|| format!("removing {file:?}"))?;
+        }
+        let Some((mode, id)) = new.get(path) else {
+            log::debug!("Removed {file:?}");
+            continue;
+        };
+        let data = repo
+            .find_object(*id)
+            .with_describe(|| format!("finding blob for {file:?}"))?
+            .detach()
+            .data;
+
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)
+                .with_describe(|| format!("creating directory {parent:?}"))?;
+        }
+        write_entry(&file, *mode, &data)?;
+        log::debug!("Updated {file:?}");
+    }
+
+    Ok(())
+}
+
+/// Writes the file to the object database and returns its tree entry
+fn stage_file(repo: &Repository, file: &Path) -> Result<(EntryMode, ObjectId)> {
+    let (kind, data) = if file.is_symlink() {
+        let target = fs::read_link(file).with_describe(|| format!("reading link {file:?}"))?;
+        (EntryKind::Link, gix::path::into_bstr(target).to_vec())
+    } else {
+        let data = fs::read(file).with_describe(|| format!("reading {file:?}"))?;
+        (file_kind(file)?, data)
+    };
+    let id = repo
+        .write_blob(data)
+        .with_describe(|| format!("writing blob for {file:?}"))?
+        .detach();
+
+    Ok((kind.into(), id))
+}
+
+#[cfg(unix)]
+fn file_kind(file: &Path) -> Result<EntryKind> {
+    use std::os::unix::fs::PermissionsExt;
+    let metadata = fs::metadata(file).with_describe(|| format!("reading metadata of {file:?}"))?;
+
+    if metadata.permissions().mode() & 0o111 != 0 {
+        Ok(EntryKind::BlobExecutable)
+    } else {
+        Ok(EntryKind::Blob)
+    }
+}
+
+#[cfg(windows)]
+fn file_kind(_file: &Path) -> Result<EntryKind> {
+    Ok(EntryKind::Blob)
+}
+
+/// Writes the trees for the given files and returns the id of the root tree
+fn write_tree(repo: &Repository, files: &TreeEntries) -> Result<ObjectId> {
+    let mut dirs: BTreeMap<BString, TreeEntries> = BTreeMap::new();
+    let mut tree = Tree::empty();
+
+    for (path, (mode, id)) in files {
+        match path.find_byte(b'/') {
+            Some(i) => {
+                dirs.entry(path[..i].into())
+                    .or_default()
+                    .insert(path[i + 1..].into(), (*mode, *id));
+            }
+            None => tree.entries.push(tree::Entry {
+                mode: *mode,
+                filename: path.clone(),
+                oid: *id,
+            }),
+        }
+    }
+    for (name, files) in dirs {
+        tree.entries.push(tree::Entry {
+            mode: EntryKind::Tree.into(),
+            filename: name,
+            oid: write_tree(repo, &files)?,
+        });
+    }
+    tree.entries.sort();
+
+    Ok(repo.write_object(&tree).describe("writing tree")?.detach())
+}
+
+/// Returns the object id the file would have if it was added to the repo
+fn worktree_id(repo: &Repository, file: &Path) -> Result<Option<ObjectId>> {
+    let data = if file.is_symlink() {
+        let target = fs::read_link(file).with_describe(|| format!("reading link {file:?}"))?;
+        gix::path::into_bstr(target).to_vec()
+    } else if file.is_file() {
+        fs::read(file).with_describe(|| format!("reading {file:?}"))?
+    } else {
+        return Ok(None);
+    };
+
+    Ok(Some(gix::objs::compute_hash(
+        repo.object_hash(),
+        gix::objs::Kind::Blob,
+        &data,
+    )))
+}
+
+fn write_entry(file: &Path, mode: EntryMode, data: &[u8]) -> Result<()> {
+    if mode.is_link() {
+        let target = gix::path::from_bstr(data.as_bstr()).into_owned();
+        return create_symlink(&target, file);
+    }
+    fs::write(file, data).with_describe(|| format!("writing {file:?}"))?;
+
+    #[cfg(unix)]
+    if mode.is_executable() {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(file, fs::Permissions::from_mode(0o755))
+            .with_describe(|| format!("setting permissions on {file:?}"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &Path, file: &Path) -> Result<()> {
+    std::os::unix::fs::symlink(target, file).with_describe(|| format!("creating link {file:?}"))
+}
+
+#[cfg(windows)]
+fn create_symlink(target: &Path, file: &Path) -> Result<()> {
+    std::os::windows::fs::symlink_file(target, file)
+        .with_describe(|| format!("creating link {file:?}"))
+}
+
+/// Returns true if the index differs from the given tree of the last commit
+fn has_staged_changes(repo: &Repository, committed: &TreeEntries) -> Result<bool> {
+    let index = repo.index_or_empty().describe("reading index")?;
+
+    if index.entries().len() != committed.len() {
+        return Ok(true);
+    }
+    let staged = index
+        .entries()
+        .iter()
+        .any(|entry| committed.get(entry.path(&index)).map(|(_, id)| *id) != Some(entry.id));
+
+    Ok(staged)
+}
+
+/// Replaces the index with the contents of the given commit
+fn write_index(repo: &Repository, commit: ObjectId) -> Result<()> {
+    let tree = repo
+        .find_object(commit)
+        .describe("finding commit")?
+        .peel_to_tree()
+        .describe("reading commit tree")?;
+    let state = gix::index::State::from_tree(&tree.id, &repo.objects).describe("building index")?;
+    let mut index = gix::index::File::from_state(state, repo.index_path());
+
+    index
+        .write(Default::default())
+        .into_diagnostic()
+        .map_err(|e| e.context("writing index"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+        process::Command,
+    };
+
+    use super::{commit, status, update};
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(dir)
+            .args(["-c", "user.name=test", "-c", "user.email=test@localhost"])
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed");
+
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    }
+
+    /// Creates a bare remote with one commit and a clone of it
+    fn setup(dir: &Path) -> (PathBuf, PathBuf) {
+        let remote = dir.join("remote.git");
+        let upstream = dir.join("upstream");
+        let local = dir.join("local");
+        git(dir, &["init", "--quiet", "--bare", "remote.git"]);
+        git(dir, &["clone", "--quiet", "remote.git", "upstream"]);
+        fs::write(upstream.join("a.txt"), "a").unwrap();
+        git(&upstream, &["add", "--all"]);
+        git(&upstream, &["commit", "--quiet", "--message", "first"]);
+        git(&upstream, &["push", "--quiet", "origin", "HEAD"]);
+        git(
+            dir,
+            &["clone", "--quiet", remote.to_str().unwrap(), "local"],
+        );
+
+        (upstream, local)
+    }
+
+    #[test]
+    fn it_fast_forwards_from_a_bare_remote() {
+        let dir = tempfile::tempdir().unwrap();
+        let (upstream, local) = setup(dir.path());
+        fs::write(upstream.join("a.txt"), "changed").unwrap();
+        fs::write(upstream.join("b.txt"), "b").unwrap();
+        git(&upstream, &["add", "--all"]);
+        git(&upstream, &["commit", "--quiet", "--message", "second"]);
+        git(&upstream, &["push", "--quiet", "origin", "HEAD"]);
+
+        assert!(update(&local).unwrap());
+        assert_eq!(fs::read_to_string(local.join("a.txt")).unwrap(), "changed");
+        assert_eq!(fs::read_to_string(local.join("b.txt")).unwrap(), "b");
+        assert_eq!(
+            git(&local, &["rev-parse", "HEAD"]),
+            git(&upstream, &["rev-parse", "HEAD"])
+        );
+        assert_eq!(git(&local, &["status", "--porcelain"]), "");
+        assert!(!update(&local).unwrap());
+    }
+
+    #[test]
+    fn it_refuses_to_drop_staged_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (upstream, local) = setup(dir.path());
+        fs::write(upstream.join("b.txt"), "b").unwrap();
+        git(&upstream, &["add", "--all"]);
+        git(&upstream, &["commit", "--quiet", "--message", "second"]);
+        git(&upstream, &["push", "--quiet", "origin", "HEAD"]);
+        fs::write(local.join("c.txt"), "c").unwrap();
+        git(&local, &["add", "c.txt"]);
+        let head = git(&local, &["rev-parse", "HEAD"]);
+
+        let error = update(&local).unwrap_err();
+        assert!(error.to_string().contains("staged changes"));
+        assert_eq!(git(&local, &["rev-parse", "HEAD"]), head);
+        assert_eq!(git(&local, &["status", "--porcelain"]), "A  c.txt\n");
+    }
+
+    #[test]
+    fn it_commits_all_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, local) = setup(dir.path());
+        fs::create_dir_all(local.join("nested").join("dir")).unwrap();
+        fs::write(local.join("nested").join("dir").join("b.txt"), "b").unwrap();
+        fs::remove_file(local.join("a.txt")).unwrap();
+
+        assert!(commit(&local, Some("change files")).unwrap());
+        assert_eq!(git(&local, &["status", "--porcelain"]), "");
+        assert_eq!(
+            git(&local, &["show", "--name-status", "--format=%s", "HEAD"]),
+            "change files\n\nD\ta.txt\nA\tnested/dir/b.txt\n"
+        );
+        git(&local, &["fsck", "--strict"]);
+        assert!(!commit(&local, None).unwrap());
+    }
+
+    #[test]
+    fn it_counts_commits_ahead_and_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let (upstream, local) = setup(dir.path());
+
+        for i in 0..2 {
+            fs::write(upstream.join("b.txt"), i.to_string()).unwrap();
+            git(&upstream, &["add", "--all"]);
+            git(&upstream, &["commit", "--quiet", "--message", "upstream"]);
+        }
+        git(&upstream, &["push", "--quiet", "origin", "HEAD"]);
+        fs::write(local.join("c.txt"), "c").unwrap();
+        git(&local, &["add", "--all"]);
+        git(&local, &["commit", "--quiet", "--message", "local"]);
+        git(&local, &["fetch", "--quiet"]);
+
+        let status = status(&local).unwrap();
+        assert_eq!((status.ahead, status.behind), (1, 2));
+        assert!(status.upstream.is_some());
+    }
+}
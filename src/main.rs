@@ -1,16 +1,24 @@
-use std::{fs, sync::atomic::AtomicBool};
+use std::{env, fs, process, sync::atomic::AtomicBool};
 
-use args::{Args, InitArgs};
+use args::{
+    AddArgs, ApplyArgs, Args, CommitArgs, ConfigArgs, DiffArgs, InitArgs, ReAddArgs, RepoArgs,
+    RestoreArgs, UpdateArgs,
+};
+use backup::BackupRun;
 use clap::Parser;
 use gix::progress::Discard;
-use miette::{Context, IntoDiagnostic, Result};
-use repo::SiloRepo;
+use miette::{bail, Context, IntoDiagnostic, Result};
+use repo::{ApplyFilter, SiloRepo};
+use utils::normalize;
 
 mod args;
+mod backup;
 mod config;
 mod fs_access;
+mod git;
 mod repo;
 mod scripting;
+mod state;
 mod templating;
 
 pub(crate) mod utils;
@@ -21,23 +29,90 @@ fn main() -> Result<()> {
 
     match &args.command {
         args::Command::Init(init_args) => init(&args, init_args)?,
-        args::Command::Apply => apply(&args)?,
+        args::Command::Apply(apply_args) => apply(&args, apply_args)?,
+        args::Command::Update(update_args) => update(&args, update_args)?,
+        args::Command::Commit(commit_args) => commit(&args, commit_args)?,
+        args::Command::Push => git::push(&args.repo)?,
+        args::Command::Add(add_args) => add(&args, add_args)?,
+        args::Command::ReAdd(re_add_args) => re_add(&args, re_add_args)?,
+        args::Command::Diff(diff_args) => diff(&args, diff_args)?,
+        args::Command::Status => status(&args)?,
+        args::Command::Restore(restore_args) => restore(restore_args)?,
         args::Command::Context => {
-            let repo = SiloRepo::open(&args.repo)?;
+            let repo = SiloRepo::open(&args.repo, args.profile.as_deref())?;
             println!(
                 "{}",
-                serde_json::to_string_pretty(&templating::context(repo.config.userdata))
-                    .into_diagnostic()?
+                serde_json::to_string_pretty(&templating::context(
+                    repo.config.userdata,
+                    repo.config.profile.as_deref()
+                ))
+                .into_diagnostic()?
             )
         }
-        args::Command::Repo => {
-            println!("{}", args.repo.to_string_lossy())
+        args::Command::Config(config_args) => config(&args, config_args)?,
+        args::Command::Repo(repo_args) => repo(&args, repo_args)?,
+    }
+
+    Ok(())
+}
+
+fn config(args: &Args, config_args: &ConfigArgs) -> Result<()> {
+    let figment = config::config_figment(&args.repo, args.profile.as_deref())?;
+    let key = config_args.key.as_deref();
+
+    if config_args.explain {
+        let values = config::explain(&figment, key)?;
+        let width = values.iter().map(|v| v.key.len()).max().unwrap_or_default();
+
+        for value in values {
+            println!(
+                "{:<width$}  {}  ({})",
+                value.key,
+                serde_json::to_string(&value.value).into_diagnostic()?,
+                value.provider
+            );
         }
+    } else {
+        let value = match key {
+            Some(key) => figment
+                .find_value(key)
+                .into_diagnostic()
+                .context("finding config key")?,
+            None => figment
+                .extract()
+                .into_diagnostic()
+                .context("parsing config file")?,
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&value).into_diagnostic()?
+        );
     }
 
     Ok(())
 }
 
+fn repo(args: &Args, repo_args: &RepoArgs) -> Result<()> {
+    if repo_args.status {
+        print!("{}", git::status(&args.repo)?);
+    } else {
+        println!("{}", args.repo.to_string_lossy());
+    }
+
+    Ok(())
+}
+
+/// Warns if the repo has uncommitted changes or differs from its remote
+fn warn_repo_status(args: &Args) {
+    match git::status(&args.repo) {
+        Ok(status) => status
+            .warnings()
+            .into_iter()
+            .for_each(|w| log::warn!("{w}")),
+        Err(e) => log::debug!("Failed to read the repo status: {e:?}"),
+    }
+}
+
 fn init_logging(verbose: bool) {
     let mut builder = pretty_env_logger::formatted_builder();
     let builder = if verbose {
@@ -52,10 +127,134 @@ fn init_logging(verbose: bool) {
         .init();
 }
 
-fn apply(args: &Args) -> Result<()> {
-    let mut repo = SiloRepo::open(&args.repo)?;
-    repo.apply()?;
-    log::info!("Applied all configurations in {:?}", args.repo);
+fn apply(args: &Args, apply_args: &ApplyArgs) -> Result<()> {
+    let mut repo = SiloRepo::open(&args.repo, args.profile.as_deref())?;
+    warn_repo_status(args);
+
+    if let Some(policy) = apply_args.policy.policy() {
+        repo.config.apply_policy = policy;
+    }
+    let filter = if apply_args.targets.is_empty() {
+        None
+    } else {
+        Some(ApplyFilter::new(&args.repo, &apply_args.targets)?)
+    };
+    repo.apply(apply_args.dry_run, filter.as_ref())?;
+
+    if !apply_args.dry_run {
+        log::info!("Applied all configurations in {:?}", args.repo);
+    }
+
+    Ok(())
+}
+
+fn update(args: &Args, update_args: &UpdateArgs) -> Result<()> {
+    git::update(&args.repo)?;
+
+    if update_args.apply {
+        let apply_args = ApplyArgs {
+            policy: update_args.policy.clone(),
+            ..Default::default()
+        };
+        apply(args, &apply_args)?;
+    }
+
+    Ok(())
+}
+
+fn commit(args: &Args, commit_args: &CommitArgs) -> Result<()> {
+    git::commit(&args.repo, commit_args.message.as_deref())?;
+
+    if commit_args.push {
+        git::push(&args.repo)?;
+    }
+
+    Ok(())
+}
+
+fn add(args: &Args, add_args: &AddArgs) -> Result<()> {
+    let repo = SiloRepo::open(&args.repo, args.profile.as_deref())?;
+    let path = normalize(&env::current_dir().into_diagnostic()?.join(&add_args.path));
+
+    repo.add(&path, add_args.template, add_args.recursive)
+}
+
+fn re_add(args: &Args, re_add_args: &ReAddArgs) -> Result<()> {
+    let repo = SiloRepo::open(&args.repo, args.profile.as_deref())?;
+    let cwd = env::current_dir().into_diagnostic()?;
+    let paths: Vec<_> = re_add_args
+        .paths
+        .iter()
+        .map(|p| normalize(&cwd.join(p)))
+        .collect();
+
+    repo.re_add(&paths)
+}
+
+fn diff(args: &Args, diff_args: &DiffArgs) -> Result<()> {
+    let mut repo = SiloRepo::open(&args.repo, args.profile.as_deref())?;
+    let changed = repo.diff(diff_args.tool)?;
+
+    if changed > 0 {
+        log::info!("{changed} file(s) would change");
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn status(args: &Args) -> Result<()> {
+    let repo = SiloRepo::open(&args.repo, args.profile.as_deref())?;
+
+    for (path, status) in repo.status()? {
+        println!("{:<15}{}", status.label(), path.display());
+    }
+
+    Ok(())
+}
+
+fn restore(restore_args: &RestoreArgs) -> Result<()> {
+    if restore_args.list {
+        for id in BackupRun::list()? {
+            println!("{id}");
+
+            for path in BackupRun::open(&id)?.paths() {
+                println!("  {}", path.display());
+            }
+        }
+        return Ok(());
+    }
+    let path = restore_args
+        .path
+        .as_ref()
+        .map(|p| env::current_dir().map(|cwd| normalize(&cwd.join(p))))
+        .transpose()
+        .into_diagnostic()?;
+
+    let run = if let Some(id) = &restore_args.run {
+        BackupRun::open(id)?
+    } else {
+        let mut runs = BackupRun::list()?
+            .into_iter()
+            .rev()
+            .map(|id| BackupRun::open(&id));
+        let found = match &path {
+            Some(path) => runs.find(|r| r.as_ref().map_or(true, |r| r.contains(path))),
+            None => runs.next(),
+        };
+        match found {
+            Some(run) => run?,
+            None => bail!("No backup found"),
+        }
+    };
+
+    if let Some(path) = path {
+        run.restore(&path)?;
+    } else {
+        for path in run.paths() {
+            run.restore(path)?;
+        }
+    }
 
     Ok(())
 }
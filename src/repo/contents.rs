@@ -6,7 +6,7 @@ use std::{
 
 use crate::{config::SiloConfig, scripting::create_lua, templating, utils::Describe};
 
-use super::{ApplyContext, ParseContext, ReadMode};
+use super::{ApplyContext, ParseContext, PendingFile, ReadMode};
 use globset::{Glob, GlobSet, GlobSetBuilder};
 use lazy_static::lazy_static;
 use miette::{Context, IntoDiagnostic, Result};
@@ -24,9 +24,25 @@ impl Contents {
         Ok(Self { root })
     }
 
-    pub fn apply(&self, actx: &mut ApplyContext, cwd: &Path) -> Result<()> {
+    pub fn apply(&self, actx: &mut ApplyContext<'_>, cwd: &Path) -> Result<()> {
         self.root.apply(actx, cwd)
     }
+
+    /// Returns the source directory and the rendered destination of the
+    /// innermost root that contains the given destination path
+    pub fn find_root(
+        &self,
+        config: &SiloConfig,
+        path: &Path,
+    ) -> Result<Option<(PathBuf, PathBuf)>> {
+        let mut roots = Vec::new();
+        self.root.collect_roots(config, &mut roots)?;
+
+        Ok(roots
+            .into_iter()
+            .filter(|(_, dst)| path.starts_with(dst))
+            .max_by_key(|(_, dst)| dst.components().count()))
+    }
 }
 
 lazy_static! {
@@ -116,7 +132,29 @@ impl DirEntry {
         }
     }
 
-    fn apply(&self, ctx: &mut ApplyContext, cwd: &Path) -> Result<()> {
+    fn collect_roots(
+        &self,
+        config: &SiloConfig,
+        roots: &mut Vec<(PathBuf, PathBuf)>,
+    ) -> Result<()> {
+        let children = match self {
+            DirEntry::File(_) => return Ok(()),
+            DirEntry::Dir(_, children) => children,
+            DirEntry::Root(p, data, children) => {
+                let rendered_path = templating::render(&data.path, &data.source, config, config)?;
+                roots.push((p.to_owned(), PathBuf::from(rendered_path)));
+                children
+            }
+        };
+
+        for child in children {
+            child.collect_roots(config, roots)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply(&self, ctx: &mut ApplyContext<'_>, cwd: &Path) -> Result<()> {
         match self {
             DirEntry::File(file) => file.apply(ctx, cwd),
             DirEntry::Dir(p, children) => {
@@ -131,12 +169,15 @@ impl DirEntry {
                 Ok(())
             }
             DirEntry::Root(_, data, children) => {
-                let rendered_path = templating::render(&data.path, &ctx.config)?;
+                let rendered_path = ctx.engine.render(&data.path, &data.source, &ctx.config)?;
                 let cwd = PathBuf::from(rendered_path);
+                let link = ctx.link;
+                ctx.link = data.link.unwrap_or(link);
 
                 for child in children {
                     child.apply(ctx, &cwd)?;
                 }
+                ctx.link = link;
                 Ok(())
             }
         }
@@ -160,26 +201,42 @@ impl FileEntry {
         }
     }
 
-    fn apply(&self, ctx: &mut ApplyContext, cwd: &Path) -> Result<()> {
+    fn apply(&self, ctx: &mut ApplyContext<'_>, cwd: &Path) -> Result<()> {
         match self {
             FileEntry::Template(path) => {
                 log::debug!("Processing template {path:?}");
 
-                let contents = fs::read_to_string(path).into_diagnostic()?;
                 let new_path = path.with_extension("");
                 let filename = new_path.file_name().unwrap();
-
                 let dest = cwd.join(filename);
-                let render_contents = templating::render(&contents, &ctx.config.userdata)?;
 
-                ctx.fs.write_all(&dest, &render_contents.into_bytes())?;
-                ctx.fs
-                    .set_permissions(&dest, fs::metadata(path).into_diagnostic()?.permissions())?;
+                if !ctx.is_selected(path, &dest) {
+                    return Ok(());
+                }
+                ctx.pending.push(PendingFile::Template {
+                    src: path.to_owned(),
+                    dst: dest,
+                });
             }
             FileEntry::Plain(path) => {
                 let filename = path.file_name().unwrap();
                 let dest = cwd.join(filename);
-                ctx.fs.copy(path, &dest)?;
+
+                if !ctx.is_selected(path, &dest) {
+                    return Ok(());
+                }
+
+                if ctx.link {
+                    let target = fs::canonicalize(path)
+                        .into_diagnostic()
+                        .with_context(|| format!("resolving path {path:?}"))?;
+                    ctx.pending.push(PendingFile::Symlink { target, dst: dest });
+                } else {
+                    ctx.pending.push(PendingFile::Copy {
+                        src: path.to_owned(),
+                        dst: dest,
+                    });
+                }
             }
         }
 
@@ -196,40 +253,45 @@ pub struct RootDirData {
     pub exclude: GlobSet,
     #[serde(default)]
     pub include: GlobSet,
+    /// Deploy plain files as symlinks into the repo
+    #[serde(default)]
+    pub link: Option<bool>,
+    /// The metadata file the data was read from
+    #[serde(skip)]
+    pub source: PathBuf,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Default, Deserialize)]
 pub enum Mode {
     #[serde(alias = "include")]
     Include,
+    #[default]
     #[serde(alias = "exclude")]
     Exclude,
 }
 
-impl Default for Mode {
-    fn default() -> Self {
-        Self::Exclude
-    }
-}
-
 impl RootDirData {
     fn read(path: &Path) -> Result<Self> {
         let contents = fs::read_to_string(path)
             .into_diagnostic()
             .with_context(|| format!("reading metadata file {path:?}"))?;
-        toml::from_str(&contents)
+        let data: Self = toml::from_str(&contents)
             .into_diagnostic()
-            .with_context(|| format!("parsing metadata file {path:?}"))
+            .with_context(|| format!("parsing metadata file {path:?}"))?;
+
+        Ok(data.with_source(path))
     }
 
     fn read_template(path: &Path, cfg: &SiloConfig) -> Result<Self> {
         let contents = fs::read_to_string(path)
             .into_diagnostic()
             .with_context(|| format!("reading metadata file {path:?}"))?;
-        let rendered = templating::render(&contents, cfg)?;
-        toml::from_str(&rendered)
+        let rendered = templating::render(&contents, path, cfg, cfg)?;
+        let data: Self = toml::from_str(&rendered)
             .into_diagnostic()
-            .with_context(|| format!("parsing metadata file {path:?}"))
+            .with_context(|| format!("parsing metadata file {path:?}"))?;
+
+        Ok(data.with_source(path))
     }
 
     fn read_lua(path: &Path, cfg: &SiloConfig) -> Result<Self> {
@@ -238,7 +300,14 @@ impl RootDirData {
             .from_value(lua.load(path).eval().describe("evaluating script")?)
             .describe("deserialize lua value")?;
 
-        Ok(cfg)
+        Ok(cfg.with_source(path))
+    }
+
+    fn with_source(self, path: &Path) -> Self {
+        Self {
+            source: path.to_owned(),
+            ..self
+        }
     }
 
     fn read_mode(&self) -> ReadMode {
@@ -1,9 +1,9 @@
-use mlua::{Lua, LuaSerdeExt, OwnedTable};
+use mlua::{Lua, LuaSerdeExt, RegistryKey};
 use serde::Serialize;
 use std::{
     fs, mem,
     path::{Path, PathBuf},
-    sync::Arc,
+    rc::Rc,
 };
 
 use miette::{IntoDiagnostic, Result};
@@ -12,12 +12,12 @@ use crate::{config::SiloConfig, scripting::create_lua, utils::Describe};
 
 #[derive(Clone, Debug)]
 pub struct Hooks {
-    scripts: Vec<Arc<HookScript>>,
+    scripts: Vec<Rc<HookScript>>,
 }
 
 pub struct HookScript {
     lua: Lua,
-    module: mlua::OwnedTable,
+    module: RegistryKey,
 }
 
 impl std::fmt::Debug for HookScript {
@@ -60,7 +60,7 @@ impl Hooks {
                     .is_some_and(|f| f.to_string_lossy().ends_with(".hook.lua"))
             {
                 log::debug!("Found hook {path:?}");
-                scripts.push(Arc::new(HookScript::load(config, &path)?))
+                scripts.push(Rc::new(HookScript::load(config, &path)?))
             }
         }
 
@@ -109,10 +109,13 @@ impl Hooks {
 impl HookScript {
     pub fn load(config: &SiloConfig, path: &Path) -> Result<Self> {
         let lua = create_lua(config)?;
-        let module: OwnedTable = lua
+        let module: mlua::Table = lua
             .load(path)
             .eval()
             .with_describe(|| format!("loading hook script {path:?}"))?;
+        let module = lua
+            .create_registry_value(module)
+            .describe("storing hook script module")?;
 
         Ok(Self { lua, module })
     }
@@ -134,9 +137,14 @@ impl HookScript {
     }
 
     fn call_function<S: Serialize>(&self, name: &str, ctx: &S) -> Result<()> {
-        if let Ok(hook_fn) = self.module.to_ref().get::<_, mlua::Function<'_>>(name) {
+        let module: mlua::Table = self
+            .lua
+            .registry_value(&self.module)
+            .describe("loading hook script module")?;
+
+        if let Ok(hook_fn) = module.get::<_, mlua::Function<'_>>(name) {
             hook_fn
-                .call(self.lua.to_value(&ctx).describe("Serializing context")?)
+                .call::<_, ()>(self.lua.to_value(&ctx).describe("Serializing context")?)
                 .with_describe(|| format!("Calling hook script {name}"))?;
         } else {
             log::debug!("No `before_apply_all` in script");
@@ -1,17 +1,25 @@
 mod contents;
 pub(crate) mod hooks;
 
-use globset::GlobSet;
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use miette::{bail, IntoDiagnostic, Result};
 
 use std::{
-    env,
+    collections::HashSet,
+    env, fs, mem,
     path::{Path, PathBuf},
+    process::Command,
 };
 
 use crate::{
-    config::{read_config, SiloConfig},
-    fs_access::{BufferedFsAccess, FsAccess},
+    config::{read_config, schema, ApplyPolicy, SiloConfig},
+    fs_access::{
+        ensure_interactive, print_unified_diff, BufferedFsAccess, CollectFsAccess, DiffFsAccess,
+        DryRunFsAccess, FsAccess,
+    },
+    state::{AppliedState, FileStatus},
+    templating::{Engine, TemplateError},
+    utils::Describe,
 };
 
 use self::{contents::Contents, hooks::Hooks};
@@ -25,11 +33,11 @@ pub struct SiloRepo {
 }
 
 impl SiloRepo {
-    pub fn open(path: &Path) -> Result<Self> {
+    pub fn open(path: &Path, profile: Option<&str>) -> Result<Self> {
         if !path.try_exists().into_diagnostic()? {
             bail!("The repository {path:?} does not exist");
         }
-        let config = read_config(path)?;
+        let config = read_config(path, profile)?;
         let pctx = ParseContext::new(
             path.to_owned(),
             ReadMode::Exclude(GlobSet::empty()),
@@ -56,22 +64,215 @@ impl SiloRepo {
         })
     }
 
-    pub fn apply(&mut self) -> Result<()> {
-        let cwd = dirs::home_dir().unwrap_or(env::current_dir().into_diagnostic()?);
-        let fs_access: Box<dyn FsAccess> = Box::new(BufferedFsAccess::new(
-            self.repo.clone(),
-            self.config.diff_tool.to_owned(),
-            self.hooks.take(),
-        ));
-        let mut ctx = ApplyContext {
-            config: self.config.clone(),
-            fs: fs_access,
+    /// Applies the repo. With `dry_run` set, nothing is written
+    /// and a plan of all changes is printed instead.
+    /// If a filter is passed only the matching files are applied
+    pub fn apply(&mut self, dry_run: bool, filter: Option<&ApplyFilter>) -> Result<()> {
+        if dry_run {
+            self.apply_with(&mut DryRunFsAccess::default(), filter, false)
+        } else {
+            // fail before any hook runs or file is written
+            if self.config.apply_policy == ApplyPolicy::Prompt {
+                ensure_interactive()?;
+            }
+            let mut fs_access =
+                BufferedFsAccess::new(self.repo.clone(), &self.config, self.hooks.take())?;
+            self.apply_with(&mut fs_access, filter, true)
+        }
+    }
+
+    /// Prints the differences between the rendered repo and the
+    /// files on disk. Returns the number of changed files
+    pub fn diff(&mut self, use_diff_tool: bool) -> Result<usize> {
+        let diff_tool = use_diff_tool.then(|| self.config.diff_tool.to_owned());
+        let mut fs_access = DiffFsAccess::new(diff_tool);
+        self.apply_with(&mut fs_access, None, false)?;
+
+        Ok(fs_access.changed())
+    }
+
+    /// Compares the rendered repo with the last applied state
+    /// and the current contents of all managed files
+    pub fn status(&self) -> Result<Vec<(PathBuf, FileStatus)>> {
+        let mut fs_access = CollectFsAccess::default();
+        self.apply_with(&mut fs_access, None, false)?;
+        let state = AppliedState::load()?;
+
+        fs_access
+            .take_files()
+            .into_iter()
+            .map(|f| {
+                let status = match state.status(&f.dst, &f.contents)? {
+                    // the contents match but the file still has to be replaced by a link
+                    FileStatus::Clean if f.link && !is_link_to(&f.dst, &f.src) => {
+                        FileStatus::RepoChanged
+                    }
+                    status => status,
+                };
+                Ok((f.dst.clone(), status))
+            })
+            .collect()
+    }
+
+    /// Copies an existing file or directory into the root of the repo
+    /// whose destination contains it
+    pub fn add(&self, path: &Path, template: bool, recursive: bool) -> Result<()> {
+        if !path.exists() {
+            bail!("The path {path:?} does not exist");
+        }
+        let Some((root_src, root_dst)) = self.contents.find_root(&self.config, path)? else {
+            bail!("No root directory in the repo contains the path {path:?}");
         };
+        let relative = path.strip_prefix(&root_dst).into_diagnostic()?;
+
+        if path.is_dir() {
+            if !recursive {
+                bail!(
+                    help = "use `--recursive` to add directories",
+                    "The path {path:?} is a directory"
+                );
+            }
+            add_dir(path, &root_src.join(relative), template)
+        } else {
+            add_file(path, &root_src.join(relative), template)
+        }
+    }
+
+    /// Copies changes made to the destination files back into the repo.
+    /// Templates can't be updated automatically, so the difference is shown
+    /// and the template is opened in the editor instead
+    pub fn re_add(&self, paths: &[PathBuf]) -> Result<()> {
+        let mut fs_access = CollectFsAccess::default();
+        self.apply_with(&mut fs_access, None, false)?;
+
+        for file in fs_access.take_files() {
+            if !paths.is_empty() && !paths.iter().any(|p| file.dst.starts_with(p)) {
+                continue;
+            }
+            if !file.dst.exists() {
+                log::debug!("{:?} doesn't exist", file.dst);
+                continue;
+            }
+            if file.link && is_link_to(&file.dst, &file.src) {
+                log::debug!("{:?} is linked into the repo", file.dst);
+                continue;
+            }
+            let current =
+                fs::read(&file.dst).with_describe(|| format!("reading {:?}", file.dst))?;
+
+            if current == file.contents {
+                continue;
+            }
+            if file.rendered {
+                print_unified_diff(&file.dst, &file.contents, &current);
+                edit(&file.src)?;
+            } else {
+                fs::copy(&file.dst, &file.src)
+                    .with_describe(|| format!("copying {:?} to {:?}", file.dst, file.src))?;
+                log::info!("Updated {:?} from {:?}", file.src, file.dst);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the repo through the given fs access. With `prompt` set, the values
+    /// of config keys that templates use but the config doesn't set are asked for
+    /// and saved, otherwise rendering fails on them
+    fn apply_with(
+        &self,
+        fs_access: &mut dyn FsAccess,
+        filter: Option<&ApplyFilter>,
+        prompt: bool,
+    ) -> Result<()> {
+        let cwd = dirs::home_dir().unwrap_or(env::current_dir().into_diagnostic()?);
+        let local_config = prompt.then(|| self.repo.join("repo.local.toml"));
+        let mut ctx = ApplyContext::new(
+            &self.repo,
+            self.config.clone(),
+            fs_access,
+            filter,
+            local_config,
+        )?;
         self.contents.apply(&mut ctx, &cwd)?;
+        ctx.write_pending()?;
         ctx.fs.persist()
     }
 }
 
+/// Opens a file in the editor set in `$EDITOR`
+fn edit(path: &Path) -> Result<()> {
+    let Ok(editor) = env::var("EDITOR") else {
+        log::warn!("`$EDITOR` is not set. Please update {path:?} manually");
+        return Ok(());
+    };
+    Command::new(editor)
+        .arg(path)
+        .spawn()
+        .describe("spawn editor")?
+        .wait()
+        .describe("wait for editor to exit")?;
+
+    Ok(())
+}
+
+/// Version control and cache entries that are never added to the repo
+const IGNORED_ENTRIES: &[&str] = &[".git", ".hg", ".svn", ".bzr", "__pycache__", ".DS_Store"];
+
+fn add_dir(src: &Path, dst: &Path, template: bool) -> Result<()> {
+    for entry in fs::read_dir(src).with_describe(|| format!("reading directory {src:?}"))? {
+        let entry = entry.into_diagnostic()?;
+        let path = entry.path();
+
+        if IGNORED_ENTRIES.iter().any(|i| entry.file_name() == *i) {
+            log::debug!("Skipping {path:?}");
+            continue;
+        }
+        if path.is_dir() {
+            add_dir(&path, &dst.join(entry.file_name()), template)?;
+        } else {
+            add_file(&path, &dst.join(entry.file_name()), template)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn add_file(src: &Path, dst: &Path, template: bool) -> Result<()> {
+    let dst = if template {
+        let mut file_name = dst.file_name().unwrap_or_default().to_owned();
+        file_name.push(".tmpl");
+        dst.with_file_name(file_name)
+    } else {
+        dst.to_owned()
+    };
+    if dst.exists() {
+        bail!("The file {dst:?} already exists in the repo");
+    }
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).with_describe(|| format!("creating directory {parent:?}"))?;
+    }
+    if template {
+        let contents = fs::read_to_string(src).with_describe(|| format!("reading file {src:?}"))?;
+
+        if contents.contains("{{") {
+            log::warn!("Escaped the existing `{{{{` in {src:?}");
+        }
+        fs::write(&dst, contents.replace("{{", "\\{{"))
+            .with_describe(|| format!("writing file {dst:?}"))?;
+        let permissions = fs::metadata(src)
+            .with_describe(|| format!("reading metadata of {src:?}"))?
+            .permissions();
+        fs::set_permissions(&dst, permissions)
+            .with_describe(|| format!("setting permissions of {dst:?}"))?;
+    } else {
+        fs::copy(src, &dst).with_describe(|| format!("copying {src:?} to {dst:?}"))?;
+    }
+    log::info!("Added {src:?} as {dst:?}");
+
+    Ok(())
+}
+
 pub struct ParseContext {
     mode: ReadMode,
     config: SiloConfig,
@@ -96,7 +297,186 @@ impl ParseContext {
     }
 }
 
-pub struct ApplyContext {
+pub struct ApplyContext<'a> {
     config: SiloConfig,
-    fs: Box<dyn FsAccess>,
+    fs: &'a mut dyn FsAccess,
+    /// Whether plain files in the current root are deployed as symlinks
+    link: bool,
+    /// Restricts the files that are applied
+    filter: Option<&'a ApplyFilter>,
+    /// The template engine shared by all files of the apply
+    engine: Engine,
+    /// Files that are written after all templates were rendered
+    pending: Vec<PendingFile>,
+    /// The untracked config file that answers to prompts are stored in.
+    /// Without it missing config keys aren't asked for
+    local_config: Option<PathBuf>,
+}
+
+impl<'a> ApplyContext<'a> {
+    /// Creates the context and the template engine with the
+    /// helpers and partials of the repo at the given root
+    fn new(
+        repo: &Path,
+        config: SiloConfig,
+        fs: &'a mut dyn FsAccess,
+        filter: Option<&'a ApplyFilter>,
+        local_config: Option<PathBuf>,
+    ) -> Result<Self> {
+        let mut engine = Engine::new(&config);
+        engine.register_lua_helpers(repo, &config)?;
+        engine.register_file_helpers(repo);
+        engine.register_partials(&repo.join("partials"))?;
+
+        Ok(Self {
+            link: config.link,
+            filter,
+            engine,
+            pending: Vec::new(),
+            local_config,
+            config,
+            fs,
+        })
+    }
+
+    /// Returns true if the file should be applied
+    pub fn is_selected(&self, src: &Path, dst: &Path) -> bool {
+        match self.filter {
+            Some(filter) => filter.matches(src, dst),
+            None => true,
+        }
+    }
+
+    /// Renders all pending templates in parallel and writes
+    /// the pending files in the order they were collected
+    fn write_pending(&mut self) -> Result<()> {
+        let pending = mem::take(&mut self.pending);
+        let templates: Vec<_> = pending
+            .iter()
+            .filter_map(|file| match file {
+                PendingFile::Template { src, .. } => Some(src.to_owned()),
+                _ => None,
+            })
+            .collect();
+        self.engine.register_files(&templates)?;
+        let mut rendered = self.render_templates(&templates)?.into_iter();
+
+        for file in pending {
+            match file {
+                PendingFile::Template { src, dst } => {
+                    let contents = rendered.next().unwrap_or_default();
+                    self.fs.write_all(&src, &dst, contents.as_bytes())?;
+                    self.fs.set_permissions(
+                        &dst,
+                        fs::metadata(&src)
+                            .with_describe(|| format!("reading metadata of {src:?}"))?
+                            .permissions(),
+                    )?;
+                }
+                PendingFile::Copy { src, dst } => self.fs.copy(&src, &dst)?,
+                PendingFile::Symlink { target, dst } => self.fs.symlink(&target, &dst)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Renders the templates and asks for the values of config keys
+    /// that are used by templates but not set if answers can be saved.
+    /// The templates are rendered again after each answer
+    fn render_templates(&mut self, templates: &[PathBuf]) -> Result<Vec<String>> {
+        let mut prompted = HashSet::new();
+
+        loop {
+            let err = match self.engine.render_files(templates, &self.config.userdata) {
+                Ok(rendered) => return Ok(rendered),
+                Err(err) => err,
+            };
+            let key = err
+                .downcast_ref::<TemplateError>()
+                .and_then(TemplateError::missing_config_key)
+                .map(String::from);
+
+            match (key, &self.local_config) {
+                (Some(key), Some(local_config))
+                    if prompted.insert(key.clone())
+                        && schema::prompt_undeclared(
+                            &key,
+                            &mut self.config.userdata,
+                            local_config,
+                        )? => {}
+                _ => return Err(err),
+            }
+        }
+    }
+}
+
+/// A file that is deployed once all templates are rendered
+enum PendingFile {
+    Template { src: PathBuf, dst: PathBuf },
+    Copy { src: PathBuf, dst: PathBuf },
+    Symlink { target: PathBuf, dst: PathBuf },
+}
+
+/// Selects files by destination path, source path or glob
+#[derive(Clone, Debug)]
+pub struct ApplyFilter {
+    globs: GlobSet,
+    paths: Vec<PathBuf>,
+    repo: PathBuf,
+    home: PathBuf,
+    cwd: PathBuf,
+}
+
+impl ApplyFilter {
+    /// Creates a filter from a list of targets. Targets containing glob characters
+    /// are matched against the destination, the home relative destination
+    /// and the repo relative source paths.
+    /// All other targets select every file in or below the given destination
+    /// or source path
+    pub fn new(repo: &Path, targets: &[String]) -> Result<Self> {
+        let cwd = env::current_dir().into_diagnostic()?;
+        let home = dirs::home_dir().unwrap_or_else(|| cwd.clone());
+        let mut globs = GlobSetBuilder::new();
+        let mut paths = Vec::new();
+
+        for target in targets {
+            let target = match target.strip_prefix("~/") {
+                Some(rest) => home.join(rest).to_string_lossy().into_owned(),
+                None => target.to_owned(),
+            };
+
+            if target.contains(['*', '?', '[', '{']) {
+                globs.add(Glob::new(&target).with_describe(|| format!("parsing glob {target:?}"))?);
+            } else {
+                paths.push(cwd.join(&target));
+                paths.push(cwd.join(repo).join(&target));
+            }
+        }
+
+        Ok(Self {
+            globs: globs.build().describe("building target globs")?,
+            paths,
+            repo: cwd.join(repo),
+            home,
+            cwd,
+        })
+    }
+
+    pub fn matches(&self, src: &Path, dst: &Path) -> bool {
+        let src = self.cwd.join(src);
+        let relative_src = src.strip_prefix(&self.repo).unwrap_or(&src);
+        let relative_dst = dst.strip_prefix(&self.home).unwrap_or(dst);
+
+        self.paths
+            .iter()
+            .any(|p| dst.starts_with(p) || src.starts_with(p))
+            || self.globs.is_match(dst)
+            || self.globs.is_match(relative_dst)
+            || self.globs.is_match(relative_src)
+    }
+}
+
+fn is_link_to(dst: &Path, target: &Path) -> bool {
+    fs::read_link(dst).is_ok_and(|t| t == target)
 }
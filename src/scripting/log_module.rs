@@ -1,7 +1,7 @@
 use log::Level;
 use mlua::{Error, Lua, Result, Table};
 
-pub fn log_module(lua: &Lua) -> Result<Table> {
+pub fn log_module(lua: &Lua) -> Result<Table<'_>> {
     let exports = lua.create_table()?;
 
     for level in [
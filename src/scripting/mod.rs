@@ -25,3 +25,17 @@ pub fn create_lua(config: &SiloConfig) -> Result<Lua> {
 
     Ok(lua)
 }
+
+/// Creates the lua state for a config script. The script builds on the given
+/// config, which it finds in `silo.default_config` instead of the defaults
+pub fn create_config_lua(config: &SiloConfig) -> Result<Lua> {
+    let lua = create_lua(config)?;
+    lua.globals()
+        .set(
+            "__silo_default_config",
+            lua.to_value(config).describe("serializing config to lua")?,
+        )
+        .describe("registering default config")?;
+
+    Ok(lua)
+}
@@ -20,7 +20,7 @@ macro_rules! module {
 }
 
 /// Utility functions
-pub fn path_module(lua: &Lua) -> Result<Table> {
+pub fn path_module(lua: &Lua) -> Result<Table<'_>> {
     let exports = module!(lua,
         "join" => PathModule::join,
         "exists" => PathModule::exists,
@@ -1,8 +1,12 @@
+use std::io::{self, IsTerminal};
+
+use dialoguer::{Confirm, Input};
 use mlua::{Lua, LuaSerdeExt, Result, Table};
+use serde::{de::DeserializeOwned, Serialize};
 
-use crate::{config::SiloConfig, templating::ContextData};
+use crate::{config::SiloConfig, state::PromptAnswers, templating::ContextData};
 
-pub fn silo_module(lua: &Lua) -> Result<Table> {
+pub fn silo_module(lua: &Lua) -> Result<Table<'_>> {
     let silo_ctx = ContextData::default();
     let exports = lua.create_table()?;
 
@@ -10,8 +14,75 @@ pub fn silo_module(lua: &Lua) -> Result<Table> {
     exports.set("flags", lua.to_value(&silo_ctx.flags)?)?;
     exports.set("system", lua.to_value(&silo_ctx.system)?)?;
     let config = lua.globals().get::<_, mlua::Value>("__silo_config")?;
+
+    if let mlua::Value::Table(config) = &config {
+        exports.set("profile", config.get::<_, mlua::Value>("profile")?)?;
+    }
     exports.set("config", config)?;
-    exports.set("default_config", lua.to_value(&SiloConfig::default())?)?;
+    let default_config = match lua.globals().get("__silo_default_config")? {
+        mlua::Value::Nil => lua.to_value(&SiloConfig::default())?,
+        default_config => default_config,
+    };
+    exports.set("default_config", default_config)?;
+    exports.set("prompt_string", lua.create_function(lua_prompt_string)?)?;
+    exports.set("prompt_bool", lua.create_function(lua_prompt_bool)?)?;
 
     Ok(exports)
 }
+
+/// Asks the user for a string with an optional default
+fn lua_prompt_string(_: &Lua, (prompt, default): (String, Option<String>)) -> Result<String> {
+    remembered(&prompt, || {
+        ensure_interactive()?;
+        let mut input = Input::new().with_prompt(&prompt);
+
+        if let Some(default) = default {
+            input = input.default(default);
+        }
+
+        input.interact_text().map_err(mlua::Error::external)
+    })
+}
+
+/// Asks the user for a yes or no answer with an optional default
+fn lua_prompt_bool(_: &Lua, (prompt, default): (String, Option<bool>)) -> Result<bool> {
+    remembered(&prompt, || {
+        ensure_interactive()?;
+        let mut confirm = Confirm::new().with_prompt(&prompt);
+
+        if let Some(default) = default {
+            confirm = confirm.default(default);
+        }
+
+        confirm.interact().map_err(mlua::Error::external)
+    })
+}
+
+/// Returns the stored answer to the prompt or asks for it and stores the answer
+fn remembered<T: Serialize + DeserializeOwned>(
+    prompt: &str,
+    ask: impl FnOnce() -> Result<T>,
+) -> Result<T> {
+    let mut answers = PromptAnswers::load().map_err(|e| mlua::Error::runtime(format!("{e:?}")))?;
+
+    if let Some(answer) = answers.get(prompt) {
+        return Ok(answer);
+    }
+    let answer = ask()?;
+    answers
+        .insert(prompt, &answer)
+        .and_then(|_| answers.save())
+        .map_err(|e| mlua::Error::runtime(format!("{e:?}")))?;
+
+    Ok(answer)
+}
+
+fn ensure_interactive() -> Result<()> {
+    if !io::stdin().is_terminal() {
+        return Err(mlua::Error::runtime(
+            "Cannot prompt for input because stdin is not a terminal",
+        ));
+    }
+
+    Ok(())
+}
@@ -1,5 +1,5 @@
 use std::{
-    fs,
+    fs, io,
     process::{Command, Stdio},
 };
 
@@ -8,7 +8,7 @@ use serde::Serialize;
 use which::which;
 
 /// Utility functions
-pub fn utils_module(lua: &Lua) -> Result<Table> {
+pub fn utils_module(lua: &Lua) -> Result<Table<'_>> {
     let exports = lua.create_table()?;
 
     exports.set("merge", lua.create_function(lua_merge)?)?;
@@ -103,32 +103,38 @@ fn lua_ext(lua: &Lua, program: String) -> Result<Function<'_>> {
 }
 
 #[derive(Serialize)]
-struct CommandOutput {
-    code: i32,
-    stdout: String,
-    stderr: String,
+pub struct CommandOutput {
+    /// The exit code or `None` if the program was killed by a signal
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Runs a program with the given args and returns its trimmed output
+pub fn run_piped(program: &str, args: &[String]) -> io::Result<CommandOutput> {
+    let cmd = Command::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let output = cmd.wait_with_output()?;
+    let text = |bytes| {
+        String::from_utf8(bytes)
+            .map(|s| s.trim().into())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    };
+
+    Ok(CommandOutput {
+        code: output.status.code(),
+        stdout: text(output.stdout)?,
+        stderr: text(output.stderr)?,
+    })
 }
 
 /// Creates a new executable that can be called with a variable number of args
 fn lua_ext_piped(lua: &Lua, program: String) -> Result<Function<'_>> {
-    lua.create_function(move |lua, args| {
-        let cmd = Command::new(&program)
-            .args::<Vec<String>, _>(args)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()?;
-        let output = cmd.wait_with_output()?;
-        let output = CommandOutput {
-            code: output.status.code().unwrap_or(0),
-            stdout: String::from_utf8(output.stdout)
-                .map_err(mlua::Error::external)?
-                .trim()
-                .into(),
-            stderr: String::from_utf8(output.stderr)
-                .map_err(mlua::Error::external)?
-                .trim()
-                .into(),
-        };
+    lua.create_function(move |lua, args: Vec<String>| {
+        let output = run_piped(&program, &args)?;
 
         lua.to_value(&output)
     })
@@ -0,0 +1,238 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use chksum::{sha2_256::chksum, Chksumable};
+use miette::{bail, IntoDiagnostic, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::utils::Describe;
+
+/// Record of all files that were written by silo
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct AppliedState {
+    pub files: BTreeMap<PathBuf, AppliedFile>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AppliedFile {
+    /// The file in the repo that the destination was created from
+    pub src: PathBuf,
+    /// The checksum of the written contents
+    pub checksum: String,
+    /// Time of the write in seconds since the unix epoch
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileStatus {
+    /// The file has never been applied
+    New,
+    /// Neither the repo nor the target changed since the last apply
+    Clean,
+    /// The rendered repo contents changed since the last apply
+    RepoChanged,
+    /// The target was modified or removed since the last apply
+    TargetDrifted,
+    /// Both the repo and the target changed since the last apply
+    BothChanged,
+}
+
+impl AppliedState {
+    /// Loads the state file or returns an empty state if it doesn't exist yet
+    pub fn load() -> Result<Self> {
+        let path = state_file()?;
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents =
+            fs::read_to_string(&path).with_describe(|| format!("reading state file {path:?}"))?;
+
+        serde_json::from_str(&contents).with_describe(|| format!("parsing state file {path:?}"))
+    }
+
+    /// Saves the state file and removes stored contents that are no longer referenced
+    pub fn save(&self) -> Result<()> {
+        let path = state_file()?;
+        fs::create_dir_all(data_dir()?).describe("creating silo data directory")?;
+        let contents = serde_json::to_string_pretty(self).describe("serializing state")?;
+        fs::write(&path, contents).with_describe(|| format!("writing state file {path:?}"))?;
+
+        let objects = objects_dir()?;
+        if !objects.exists() {
+            return Ok(());
+        }
+        let referenced: HashSet<_> = self.files.values().map(|f| &f.checksum).collect();
+
+        for entry in fs::read_dir(&objects).describe("reading stored contents")? {
+            let entry = entry.into_diagnostic()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            if !referenced.contains(&name) {
+                log::debug!("Removing unreferenced object {name}");
+                fs::remove_file(entry.path())
+                    .with_describe(|| format!("removing stored contents {:?}", entry.path()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records that `dst` was written from `src` with the contents of the file `contents`.
+    /// A copy of the contents is kept as the base for future merges
+    pub fn record(&mut self, src: &Path, dst: &Path, contents: &Path) -> Result<()> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .into_diagnostic()?
+            .as_secs();
+        let checksum = checksum(contents)?;
+        let objects_dir = objects_dir()?;
+        let object = objects_dir.join(&checksum);
+
+        if !object.exists() {
+            fs::create_dir_all(&objects_dir).describe("creating object directory")?;
+            fs::copy(contents, &object)
+                .with_describe(|| format!("storing applied contents of {dst:?}"))?;
+        }
+        self.files.insert(
+            dst.to_owned(),
+            AppliedFile {
+                src: src.to_owned(),
+                checksum,
+                timestamp,
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Returns the path to the contents that were last applied to `dst`
+    pub fn base(&self, dst: &Path) -> Option<PathBuf> {
+        let objects_dir = objects_dir().ok()?;
+
+        self.files
+            .get(dst)
+            .map(|f| objects_dir.join(&f.checksum))
+            .filter(|p| p.exists())
+    }
+
+    /// Compares the last applied state of `dst` with the newly rendered contents
+    /// and the current contents of the target
+    pub fn status(&self, dst: &Path, rendered: &[u8]) -> Result<FileStatus> {
+        let Some(applied) = self.files.get(dst) else {
+            return Ok(FileStatus::New);
+        };
+        let rendered_sum = checksum(rendered)?;
+        let target_sum = if dst.exists() {
+            Some(checksum(dst)?)
+        } else {
+            None
+        };
+
+        // linked files and files that were changed to the same contents
+        // don't need to be applied
+        if target_sum.as_ref() == Some(&rendered_sum) {
+            return Ok(FileStatus::Clean);
+        }
+        let repo_changed = rendered_sum != applied.checksum;
+        let target_changed = target_sum.as_ref() != Some(&applied.checksum);
+
+        let status = match (repo_changed, target_changed) {
+            (false, false) => FileStatus::Clean,
+            (true, false) => FileStatus::RepoChanged,
+            (false, true) => FileStatus::TargetDrifted,
+            (true, true) => FileStatus::BothChanged,
+        };
+
+        Ok(status)
+    }
+}
+
+impl FileStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            FileStatus::New => "new",
+            FileStatus::Clean => "clean",
+            FileStatus::RepoChanged => "repo-changed",
+            FileStatus::TargetDrifted => "target-drifted",
+            FileStatus::BothChanged => "both-changed",
+        }
+    }
+}
+
+/// Answers to the prompts of config scripts by prompt,
+/// so that a prompt is only asked once
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct PromptAnswers {
+    answers: BTreeMap<String, serde_json::Value>,
+}
+
+impl PromptAnswers {
+    /// Loads the stored answers or returns no answers if none were stored yet
+    pub fn load() -> Result<Self> {
+        let path = prompts_file()?;
+
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents =
+            fs::read_to_string(&path).with_describe(|| format!("reading answers {path:?}"))?;
+
+        serde_json::from_str(&contents).with_describe(|| format!("parsing answers {path:?}"))
+    }
+
+    /// Writes the answers to the local data directory
+    pub fn save(&self) -> Result<()> {
+        let path = prompts_file()?;
+        fs::create_dir_all(data_dir()?).describe("creating silo data directory")?;
+        let contents = serde_json::to_string_pretty(self).describe("serializing answers")?;
+
+        fs::write(&path, contents).with_describe(|| format!("writing answers {path:?}"))
+    }
+
+    /// Returns the answer that was given to the prompt before
+    pub fn get<T: DeserializeOwned>(&self, prompt: &str) -> Option<T> {
+        serde_json::from_value(self.answers.get(prompt)?.clone()).ok()
+    }
+
+    /// Stores the answer to the prompt
+    pub fn insert<T: Serialize>(&mut self, prompt: &str, answer: &T) -> Result<()> {
+        let answer = serde_json::to_value(answer).describe("serializing answer")?;
+        self.answers.insert(prompt.to_owned(), answer);
+
+        Ok(())
+    }
+}
+
+/// The directory silo stores its local state in
+pub fn data_dir() -> Result<PathBuf> {
+    let Some(dir) = dirs::data_local_dir() else {
+        bail!(
+            help = "set `$HOME` or `$XDG_DATA_HOME`",
+            "Couldn't determine the local data directory"
+        );
+    };
+
+    Ok(dir.join("silo-state"))
+}
+
+fn state_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("applied.json"))
+}
+
+fn prompts_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("prompts.json"))
+}
+
+fn objects_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("objects"))
+}
+
+/// Returns the hex encoded sha256 checksum of the data
+pub fn checksum<C: Chksumable>(data: C) -> Result<String> {
+    Ok(chksum(data).into_diagnostic()?.to_hex_lowercase())
+}
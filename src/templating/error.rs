@@ -0,0 +1,95 @@
+use std::path::Path;
+
+use handlebars::{RenderError, RenderErrorReason};
+use miette::{Diagnostic, NamedSource, SourceSpan};
+use thiserror::Error;
+
+/// A rendering error that points to the failing location in the template
+#[derive(Debug, Error, Diagnostic)]
+#[error("Failed to render {name}: {reason}")]
+#[diagnostic(code(silo::template))]
+pub struct TemplateError {
+    name: String,
+    reason: String,
+    #[source_code]
+    src: NamedSource,
+    #[label("{label}")]
+    span: Option<SourceSpan>,
+    label: String,
+    #[help]
+    help: Option<String>,
+    /// The variable that isn't defined in strict mode
+    missing: Option<String>,
+}
+
+impl TemplateError {
+    pub fn new(path: &Path, template: &str, err: RenderError) -> Self {
+        let name = path.display().to_string();
+        let position = match err.reason() {
+            RenderErrorReason::TemplateError(e) => e.pos(),
+            _ => err.line_no.zip(err.column_no),
+        };
+        let missing = match err.reason() {
+            RenderErrorReason::MissingVariable(var) => var.clone(),
+            _ => None,
+        };
+        let (reason, label, help) = match err.reason() {
+            RenderErrorReason::MissingVariable(Some(var)) => (
+                format!("the variable `{var}` is not defined"),
+                format!("`{var}` is not defined"),
+                Some(match config_key(var) {
+                    Some(key) => format!("set `{key}` in the config or disable `strict_templates`"),
+                    None => String::from("define the variable or disable `strict_templates`"),
+                }),
+            ),
+            RenderErrorReason::TemplateError(e) => {
+                (e.reason().to_string(), String::from("here"), None)
+            }
+            reason => (reason.to_string(), String::from("here"), None),
+        };
+
+        Self {
+            span: position.map(|(line, col)| span_at(template, line, col)),
+            src: NamedSource::new(&name, template.to_owned()),
+            name,
+            reason,
+            label,
+            help,
+            missing,
+        }
+    }
+
+    /// Returns the config key that isn't set if rendering failed because of it
+    pub fn missing_config_key(&self) -> Option<&str> {
+        self.missing.as_deref().and_then(config_key)
+    }
+}
+
+/// Returns the config key of a variable that templates reach through `ctx` or `cfg`
+fn config_key(var: &str) -> Option<&str> {
+    var.strip_prefix("ctx.").or(var.strip_prefix("cfg."))
+}
+
+/// Returns the span of the expression at the 1-based line and column
+fn span_at(template: &str, line: usize, col: usize) -> SourceSpan {
+    let mut lines = template.split_inclusive('\n');
+    let line_start: usize = lines
+        .by_ref()
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let line_text = lines.next().unwrap_or_default();
+    let col_offset = line_text
+        .char_indices()
+        .nth(col.saturating_sub(1))
+        .map_or(line_text.len(), |(i, _)| i);
+    let offset = line_start + col_offset;
+    let rest = &template[offset..];
+    let len = match (rest.find("}}"), rest.find('\n')) {
+        (Some(end), Some(newline)) if end < newline => end + 2,
+        (Some(end), None) => end + 2,
+        _ => 1,
+    };
+
+    (offset, len.min(rest.len())).into()
+}
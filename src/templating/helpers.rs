@@ -1,9 +1,88 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use ::base64::{engine::general_purpose::STANDARD, Engine as _};
 use handlebars::{
-    Context, Handlebars, Helper, HelperDef, HelperResult, Output, RenderContext, RenderError,
-    RenderErrorReason, Renderable,
+    handlebars_helper, Context, Handlebars, Helper, HelperDef, HelperResult, JsonValue, Output,
+    RenderContext, RenderError, RenderErrorReason, Renderable, ScopedJson,
 };
+use handlebars_switch::SwitchHelper;
+use regex::Regex;
 use which::which;
 
+use crate::{scripting::utils_module::run_piped, state::checksum};
+
+/// Registers the helpers that are available in all templates.
+/// Comparison helpers like `eq`, `gt`, `and` and `or` and the case conversion
+/// helpers like `snakeCase` are provided by handlebars itself
+pub fn register(hb: &mut Handlebars<'_>) {
+    hb.register_helper("switch", Box::new(SwitchHelper));
+    hb.register_helper(
+        "if-installed",
+        Box::new(IfInstalledHelper { positive: true }),
+    );
+    hb.register_helper(
+        "if-not-installed",
+        Box::new(IfInstalledHelper { positive: false }),
+    );
+    hb.register_helper("env", Box::new(env));
+    hb.register_helper("upper", Box::new(upper));
+    hb.register_helper("lower", Box::new(lower));
+    hb.register_helper("replace", Box::new(replace));
+    hb.register_helper("regexReplace", Box::new(RegexReplaceHelper));
+    hb.register_helper("join", Box::new(join));
+    hb.register_helper("split", Box::new(split));
+    hb.register_helper("base64", Box::new(base64));
+    hb.register_helper("sha256", Box::new(Sha256Helper));
+    hb.register_helper("trim", Box::new(trim));
+    hb.register_helper("indent", Box::new(indent));
+    hb.register_helper("default", Box::new(DefaultHelper));
+    hb.register_helper("toJson", Box::new(to_json));
+    hb.register_helper("toToml", Box::new(ToTomlHelper));
+
+    let exec = ExecHelper::default();
+    hb.register_helper("exec", Box::new(exec.clone()));
+    hb.register_helper("output", Box::new(exec));
+}
+
+/// Registers the `include` and `render` helpers that read files from the repo
+pub fn register_file_helpers(hb: &mut Handlebars<'_>, repo: &Path) {
+    hb.register_helper(
+        "include",
+        Box::new(IncludeHelper {
+            repo: repo.to_owned(),
+        }),
+    );
+    hb.register_helper(
+        "render",
+        Box::new(RenderHelper {
+            repo: repo.to_owned(),
+        }),
+    );
+}
+
+thread_local! {
+    /// The canonical paths of the template files that are
+    /// currently rendered on this thread, starting with the entry file
+    static RENDER_STACK: RefCell<Vec<PathBuf>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Marks the template file as rendered on this thread while `render` runs,
+/// so that the `render` helper detects templates that render their entry file
+pub fn with_entry_file<T>(path: &Path, render: impl FnOnce() -> T) -> T {
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_owned());
+    RENDER_STACK.with_borrow_mut(|stack| stack.push(path));
+    let result = render();
+    RENDER_STACK.with_borrow_mut(|stack| stack.pop());
+
+    result
+}
+
 pub struct IfInstalledHelper {
     pub positive: bool,
 }
@@ -34,10 +113,696 @@ impl HelperDef for IfInstalledHelper {
             h.template()
                 .ok_or_else(|| RenderErrorReason::BlockContentRequired)?
                 .render(r, ctx, rc, out)
-                .map_err(RenderError::from)
         } else {
             log::debug!("`{bin}` is not installed");
             HelperResult::Ok(())
         }
     }
 }
+
+handlebars_helper!(env: |name: str, {default: str = ""}| {
+    std::env::var(name).unwrap_or_else(|_| default.to_owned())
+});
+handlebars_helper!(upper: |s: str| s.to_uppercase());
+handlebars_helper!(lower: |s: str| s.to_lowercase());
+handlebars_helper!(replace: |s: str, from: str, to: str| s.replace(from, to));
+handlebars_helper!(join: |list: array, separator: str| {
+    list.iter().map(to_text).collect::<Vec<_>>().join(separator)
+});
+handlebars_helper!(split: |s: str, separator: str| s.split(separator).collect::<Vec<_>>());
+handlebars_helper!(base64: |s: str| STANDARD.encode(s));
+handlebars_helper!(trim: |s: str| s.trim());
+handlebars_helper!(indent: |s: str, width: u64| {
+    let prefix = " ".repeat(width as usize);
+    s.split_inclusive('\n')
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.to_owned()
+            } else {
+                format!("{prefix}{line}")
+            }
+        })
+        .collect::<String>()
+});
+handlebars_helper!(to_json: |value: Json, {pretty: bool = false}| {
+    if pretty {
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    } else {
+        value.to_string()
+    }
+});
+
+/// Replaces all matches of a regular expression.
+/// The replacement can refer to capture groups with `$1` or `$name`
+pub struct RegexReplaceHelper;
+
+impl HelperDef for RegexReplaceHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let s = str_param(h, "regexReplace", 0)?;
+        let pattern = str_param(h, "regexReplace", 1)?;
+        let replacement = str_param(h, "regexReplace", 2)?;
+        let regex = Regex::new(pattern).map_err(|e| {
+            RenderErrorReason::Other(format!("invalid regular expression `{pattern}`: {e}"))
+        })?;
+
+        Ok(ScopedJson::Derived(JsonValue::from(
+            regex.replace_all(s, replacement).into_owned(),
+        )))
+    }
+}
+
+/// Returns the hex encoded sha256 checksum of a string
+pub struct Sha256Helper;
+
+impl HelperDef for Sha256Helper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let s = str_param(h, "sha256", 0)?;
+        let sum = checksum(s.as_bytes())
+            .map_err(|e| RenderErrorReason::Other(format!("calculating checksum: {e}")))?;
+
+        Ok(ScopedJson::Derived(JsonValue::from(sum)))
+    }
+}
+
+/// Returns the first param unless it is missing, null or an empty string
+/// and the second param otherwise
+pub struct DefaultHelper;
+
+impl HelperDef for DefaultHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let value = h
+            .param(0)
+            .filter(|p| !p.is_value_missing())
+            .map(|p| p.value())
+            .filter(|v| !v.is_null() && v.as_str() != Some(""));
+        let value = match value {
+            Some(value) => value,
+            None => h
+                .param(1)
+                .ok_or(RenderErrorReason::ParamNotFoundForIndex("default", 1))?
+                .value(),
+        };
+
+        Ok(ScopedJson::Derived(value.clone()))
+    }
+}
+
+/// Serializes a table to toml
+pub struct ToTomlHelper;
+
+impl HelperDef for ToTomlHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let value = h
+            .param(0)
+            .ok_or(RenderErrorReason::ParamNotFoundForIndex("toToml", 0))?
+            .value();
+        let toml = toml::to_string_pretty(value)
+            .map_err(|e| RenderErrorReason::Other(format!("serializing value to toml: {e}")))?;
+
+        Ok(ScopedJson::Derived(JsonValue::from(toml)))
+    }
+}
+
+/// Runs a command and returns its trimmed stdout. A single param is split into
+/// words like a shell does, otherwise the first param is the program and the rest its args.
+/// The output or failure of each command is cached for the lifetime of the registry.
+/// Templates that render in parallel wait for the first run of a command
+#[derive(Clone, Default)]
+pub struct ExecHelper {
+    cache: Arc<Mutex<HashMap<Vec<String>, Arc<CachedOutput>>>>,
+}
+
+/// The stdout of a command or a description of its failure, set by the first run
+type CachedOutput = OnceLock<Result<String, String>>;
+
+impl HelperDef for ExecHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let mut command = (0..h.params().len())
+            .map(|i| str_param(h, "exec", i).map(String::from))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if command.len() == 1 {
+            command = split_words(&command[0])?;
+        }
+        if command.is_empty() {
+            return Err(RenderErrorReason::ParamNotFoundForIndex("exec", 0).into());
+        }
+        let output = self
+            .cache
+            .lock()
+            .unwrap()
+            .entry(command.clone())
+            .or_default()
+            .clone();
+        let stdout = output
+            .get_or_init(|| run_command(&command))
+            .clone()
+            .map_err(RenderErrorReason::Other)?;
+
+        Ok(ScopedJson::Derived(JsonValue::from(stdout)))
+    }
+}
+
+/// Runs the command and returns its trimmed stdout or a description of the failure
+fn run_command(command: &[String]) -> Result<String, String> {
+    let line = command.join(" ");
+    log::debug!("Running `{line}`");
+    let output =
+        run_piped(&command[0], &command[1..]).map_err(|e| format!("running `{line}`: {e}"))?;
+
+    match output.code {
+        Some(0) => Ok(output.stdout),
+        Some(code) => Err(format!(
+            "`{line}` exited with code {code}: {}",
+            output.stderr
+        )),
+        None => Err(format!(
+            "`{line}` was killed by a signal: {}",
+            output.stderr
+        )),
+    }
+}
+
+/// Splits a command line at whitespace outside of quotes. Single quotes keep
+/// their contents verbatim, a backslash escapes the next character elsewhere
+fn split_words(line: &str) -> Result<Vec<String>, RenderError> {
+    let mut words = Vec::new();
+    let mut word = None::<String>;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => word.get_or_insert_with(String::new).extend(chars.next()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(unterminated(line)),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.extend(chars.next()),
+                        Some(c) => word.push(c),
+                        None => return Err(unterminated(line)),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+
+    Ok(words)
+}
+
+fn unterminated(line: &str) -> RenderError {
+    RenderErrorReason::Other(format!("unterminated quote in `{line}`")).into()
+}
+
+/// Inlines a file of the repo verbatim
+pub struct IncludeHelper {
+    pub repo: PathBuf,
+}
+
+impl HelperDef for IncludeHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let path = repo_file(&self.repo, str_param(h, "include", 0)?)?;
+        out.write(&read_file(&path)?)?;
+
+        Ok(())
+    }
+}
+
+/// Renders a file of the repo against the current context
+pub struct RenderHelper {
+    pub repo: PathBuf,
+}
+
+impl HelperDef for RenderHelper {
+    fn call<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        r: &'reg Handlebars<'reg>,
+        ctx: &'rc Context,
+        rc: &mut RenderContext<'reg, 'rc>,
+        out: &mut dyn Output,
+    ) -> HelperResult {
+        let path = repo_file(&self.repo, str_param(h, "render", 0)?)?;
+        let template = read_file(&path)?;
+        let modified = rc.context();
+        let ctx = modified.as_deref().unwrap_or(ctx);
+
+        RENDER_STACK.with_borrow_mut(|stack| {
+            if stack.contains(&path) {
+                let chain = stack
+                    .iter()
+                    .chain([&path])
+                    .map(|p| p.display().to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ");
+                return Err(RenderErrorReason::Other(format!(
+                    "the template {path:?} renders itself: {chain}"
+                )));
+            }
+            stack.push(path.clone());
+            Ok(())
+        })?;
+        let rendered = r.render_template_with_context(&template, ctx);
+        RENDER_STACK.with_borrow_mut(|stack| stack.pop());
+
+        // the position of nested errors refers to the rendered file
+        let rendered = rendered.map_err(|e| match e.reason() {
+            RenderErrorReason::Other(reason) => RenderErrorReason::Other(reason.to_owned()),
+            _ => RenderErrorReason::Other(format!("rendering {path:?}: {e}")),
+        })?;
+        out.write(&rendered)?;
+
+        Ok(())
+    }
+}
+
+/// Resolves a repo relative path and fails if it points outside of the repo
+fn repo_file(repo: &Path, relative: &str) -> Result<PathBuf, RenderError> {
+    let outside =
+        || RenderErrorReason::Other(format!("the path {relative:?} is outside of the repo"));
+
+    if Path::new(relative).is_absolute() {
+        return Err(outside().into());
+    }
+    let path = repo.join(relative);
+    let canonical = path
+        .canonicalize()
+        .map_err(|e| RenderErrorReason::Other(format!("reading {path:?}: {e}")))?;
+    let repo = repo
+        .canonicalize()
+        .map_err(|e| RenderErrorReason::Other(format!("reading {repo:?}: {e}")))?;
+
+    if !canonical.starts_with(repo) {
+        return Err(outside().into());
+    }
+
+    Ok(canonical)
+}
+
+fn read_file(path: &Path) -> Result<String, RenderError> {
+    fs::read_to_string(path)
+        .map_err(|e| RenderErrorReason::Other(format!("reading {path:?}: {e}")).into())
+}
+
+fn str_param<'a>(
+    h: &'a Helper<'_>,
+    helper: &'static str,
+    index: usize,
+) -> Result<&'a str, RenderError> {
+    h.param(index)
+        .ok_or(RenderErrorReason::ParamNotFoundForIndex(helper, index))?
+        .value()
+        .as_str()
+        .ok_or_else(|| {
+            RenderErrorReason::ParamTypeMismatchForName(
+                helper,
+                index.to_string(),
+                String::from("string"),
+            )
+            .into()
+        })
+}
+
+/// Returns strings without quotes and all other values as json
+fn to_text(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.to_owned(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn render(template: &str, data: JsonValue) -> String {
+        try_render(template, data).unwrap()
+    }
+
+    fn try_render(template: &str, data: JsonValue) -> Result<String, RenderError> {
+        let mut hb = Handlebars::new();
+        hb.set_strict_mode(true);
+        register(&mut hb);
+        hb.render_template(template, &data)
+    }
+
+    fn render_in_repo(repo: &Path, template: &str, data: JsonValue) -> Result<String, RenderError> {
+        let mut hb = Handlebars::new();
+        hb.set_strict_mode(true);
+        register(&mut hb);
+        register_file_helpers(&mut hb, repo);
+        hb.render_template(template, &data)
+    }
+
+    #[test]
+    fn include_and_render_files() {
+        let repo = tempfile::tempdir().unwrap();
+        fs::create_dir(repo.path().join("shared")).unwrap();
+        fs::write(
+            repo.path().join("shared/aliases.sh"),
+            "alias a=\"{{name}}\"",
+        )
+        .unwrap();
+        fs::write(repo.path().join("shared/prompt.tmpl"), "hi {{name}} & bye").unwrap();
+        let data = json!({ "name": "silo" });
+
+        assert_eq!(
+            render_in_repo(
+                repo.path(),
+                r#"{{include "shared/aliases.sh"}}"#,
+                data.clone()
+            )
+            .unwrap(),
+            "alias a=\"{{name}}\""
+        );
+        assert_eq!(
+            render_in_repo(repo.path(), r#"{{render "shared/prompt.tmpl"}}"#, data).unwrap(),
+            "hi silo & bye"
+        );
+        assert!(render_in_repo(repo.path(), r#"{{include "missing"}}"#, json!({})).is_err());
+    }
+
+    #[test]
+    fn include_rejects_paths_outside_of_the_repo() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        fs::create_dir(&repo).unwrap();
+        fs::write(dir.path().join("secret"), "secret").unwrap();
+        let absolute = dir.path().join("secret");
+
+        for path in ["../secret", absolute.to_str().unwrap()] {
+            let template = format!(r#"{{{{include "{path}"}}}}"#);
+            let err = render_in_repo(&repo, &template, json!({})).unwrap_err();
+            assert!(err.to_string().contains("outside of the repo"), "{err}");
+        }
+    }
+
+    #[test]
+    fn render_detects_recursion_of_the_entry_file() {
+        let repo = tempfile::tempdir().unwrap();
+        let entry = repo.path().join("a.tmpl");
+        fs::write(&entry, r#"{{render "a.tmpl"}}"#).unwrap();
+
+        let err = with_entry_file(&entry, || {
+            render_in_repo(repo.path(), r#"{{render "a.tmpl"}}"#, json!({}))
+        })
+        .unwrap_err();
+
+        assert!(err.to_string().contains("renders itself"), "{err}");
+    }
+
+    #[test]
+    fn render_detects_recursion() {
+        let repo = tempfile::tempdir().unwrap();
+        fs::write(repo.path().join("a.tmpl"), r#"{{render "b.tmpl"}}"#).unwrap();
+        fs::write(repo.path().join("b.tmpl"), r#"{{render "a.tmpl"}}"#).unwrap();
+
+        let err = render_in_repo(repo.path(), r#"{{render "a.tmpl"}}"#, json!({})).unwrap_err();
+
+        assert!(err.to_string().contains("renders itself"), "{err}");
+        assert!(err.to_string().contains("a.tmpl -> "), "{err}");
+    }
+
+    #[test]
+    fn exec_captures_output() {
+        assert_eq!(
+            render(r#"{{exec "echo  hello   world "}}"#, json!({})),
+            "hello world"
+        );
+        assert_eq!(
+            render(r#"{{output "sh" "-c" "echo 'a  b'"}}"#, json!({})),
+            "a  b"
+        );
+        assert_eq!(
+            render(r#"{{exec "printf '%s|%s' 'a  b' c"}}"#, json!({})),
+            "a  b|c"
+        );
+        assert!(try_render(r#"{{exec "sh -c 'echo"}}"#, json!({})).is_err());
+
+        let err =
+            try_render(r#"{{exec "sh" "-c" "echo failed >&2; exit 3"}}"#, json!({})).unwrap_err();
+        assert!(
+            err.to_string().contains("exited with code 3: failed"),
+            "{err}"
+        );
+        assert!(try_render(r#"{{exec "silo-test-missing-command"}}"#, json!({})).is_err());
+
+        let err = try_render(r#"{{exec "sh" "-c" "kill -9 $$"}}"#, json!({})).unwrap_err();
+        assert!(err.to_string().contains("killed by a signal"), "{err}");
+    }
+
+    #[test]
+    fn split_words_handles_quotes_and_escapes() {
+        assert_eq!(
+            split_words(r#" a  'b  c' "d \"e\"" f\ g ''"#).unwrap(),
+            vec!["a", "b  c", "d \"e\"", "f g", ""]
+        );
+        assert!(split_words("a 'b").is_err());
+        assert!(split_words(r#"a "b"#).is_err());
+    }
+
+    #[test]
+    fn exec_caches_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let script = format!("echo run >> {}; echo done", log.display());
+        let template =
+            format!(r#"{{{{exec "sh" "-c" "{script}"}}}} {{{{exec "sh" "-c" "{script}"}}}}"#);
+
+        assert_eq!(render(&template, json!({})), "done done");
+        assert_eq!(fs::read_to_string(&log).unwrap(), "run\n");
+    }
+
+    #[test]
+    fn exec_runs_commands_once_for_parallel_renders() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let script = format!("echo run >> {}; sleep 0.2; echo done", log.display());
+        let template = format!(r#"{{{{exec "sh" "-c" "{script}"}}}}"#);
+        let mut hb = Handlebars::new();
+        register(&mut hb);
+
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| assert_eq!(hb.render_template(&template, &()).unwrap(), "done"));
+            }
+        });
+        assert_eq!(fs::read_to_string(&log).unwrap(), "run\n");
+    }
+
+    #[test]
+    fn exec_caches_failures() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("log");
+        let script = format!("echo run >> {}; exit 1", log.display());
+        let template = format!(r#"{{{{exec "sh" "-c" "{script}"}}}}"#);
+        let mut hb = Handlebars::new();
+        register(&mut hb);
+
+        assert!(hb.render_template(&template, &()).is_err());
+        assert!(hb.render_template(&template, &()).is_err());
+        assert_eq!(fs::read_to_string(&log).unwrap(), "run\n");
+    }
+
+    #[test]
+    fn env_reads_variables_with_default() {
+        std::env::set_var("SILO_TEST_HELPER_ENV", "value");
+
+        assert_eq!(
+            render(r#"{{env "SILO_TEST_HELPER_ENV"}}"#, json!({})),
+            "value"
+        );
+        assert_eq!(
+            render(
+                r#"{{env "SILO_TEST_HELPER_UNSET" default="fallback"}}"#,
+                json!({})
+            ),
+            "fallback"
+        );
+        assert_eq!(render(r#"{{env "SILO_TEST_HELPER_UNSET"}}"#, json!({})), "");
+    }
+
+    #[test]
+    fn case_conversion() {
+        let data = json!({ "s": "hello big_world" });
+
+        assert_eq!(render("{{upper s}}", data.clone()), "HELLO BIG_WORLD");
+        assert_eq!(
+            render("{{lower (upper s)}}", data.clone()),
+            "hello big_world"
+        );
+        assert_eq!(render("{{snakeCase s}}", data.clone()), "hello_big_world");
+        assert_eq!(render("{{kebabCase s}}", data.clone()), "hello-big-world");
+        assert_eq!(
+            render("{{upperCamelCase s}}", data.clone()),
+            "HelloBigWorld"
+        );
+        assert_eq!(
+            render("{{lowerCamelCase s}}", data.clone()),
+            "helloBigWorld"
+        );
+        assert_eq!(render("{{titleCase s}}", data), "Hello Big World");
+    }
+
+    #[test]
+    fn replace_strings() {
+        assert_eq!(
+            render(r#"{{replace s "o" "0"}}"#, json!({ "s": "foo" })),
+            "f00"
+        );
+    }
+
+    #[test]
+    fn regex_replace_with_captures() {
+        assert_eq!(
+            render(
+                r#"{{regexReplace s "([a-z]+)@([a-z]+)" "$2 at $1"}}"#,
+                json!({ "s": "user@host" })
+            ),
+            "host at user"
+        );
+        assert!(try_render(r#"{{regexReplace "a" "(" ""}}"#, json!({})).is_err());
+    }
+
+    #[test]
+    fn join_and_split() {
+        assert_eq!(
+            render(r#"{{join list ", "}}"#, json!({ "list": ["a", 1, true] })),
+            "a, 1, true"
+        );
+        assert_eq!(
+            render(
+                r#"{{#each (split path ":")}}[{{this}}]{{/each}}"#,
+                json!({ "path": "/bin:/usr/bin" })
+            ),
+            "[/bin][/usr/bin]"
+        );
+    }
+
+    #[test]
+    fn encode_and_hash() {
+        assert_eq!(render(r#"{{{base64 "hello"}}}"#, json!({})), "aGVsbG8=");
+        assert_eq!(
+            render(r#"{{sha256 "hello"}}"#, json!({})),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn trim_and_indent() {
+        assert_eq!(render("[{{trim s}}]", json!({ "s": "  a b \n" })), "[a b]");
+        assert_eq!(
+            render("{{indent s 2}}", json!({ "s": "a\n\nb\n" })),
+            "  a\n\n  b\n"
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let data = json!({ "a": 1, "b": 2, "name": "x" });
+
+        assert_eq!(
+            render(r#"{{#if (eq name "x")}}y{{else}}n{{/if}}"#, data.clone()),
+            "y"
+        );
+        assert_eq!(
+            render("{{#if (gt b a)}}y{{else}}n{{/if}}", data.clone()),
+            "y"
+        );
+        assert_eq!(
+            render("{{#if (gt a b)}}y{{else}}n{{/if}}", data.clone()),
+            "n"
+        );
+        assert_eq!(
+            render("{{#if (and a b)}}y{{else}}n{{/if}}", data.clone()),
+            "y"
+        );
+        assert_eq!(
+            render("{{#if (and a false)}}y{{else}}n{{/if}}", data.clone()),
+            "n"
+        );
+        assert_eq!(render("{{#if (or false b)}}y{{else}}n{{/if}}", data), "y");
+    }
+
+    #[test]
+    fn default_values() {
+        let data = json!({ "set": "value", "empty": "", "null": null, "no": false });
+
+        assert_eq!(render(r#"{{default set "x"}}"#, data.clone()), "value");
+        assert_eq!(render(r#"{{default empty "x"}}"#, data.clone()), "x");
+        assert_eq!(render(r#"{{default null "x"}}"#, data.clone()), "x");
+        assert_eq!(render(r#"{{default missing "x"}}"#, data.clone()), "x");
+        assert_eq!(render(r#"{{default no true}}"#, data), "false");
+    }
+
+    #[test]
+    fn serialize_values() {
+        let data = json!({ "cfg": { "name": "silo", "list": [1, 2] } });
+
+        assert_eq!(
+            render("{{{toJson cfg}}}", data.clone()),
+            r#"{"list":[1,2],"name":"silo"}"#
+        );
+        assert_eq!(
+            render("{{{toJson cfg pretty=true}}}", data.clone()),
+            "{\n  \"list\": [\n    1,\n    2\n  ],\n  \"name\": \"silo\"\n}"
+        );
+        assert_eq!(
+            render("{{{toToml cfg}}}", data),
+            "list = [\n    1,\n    2,\n]\nname = \"silo\"\n"
+        );
+        assert!(try_render("{{toToml 1}}", json!({})).is_err());
+    }
+}
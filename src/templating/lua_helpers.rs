@@ -0,0 +1,176 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, RenderErrorReason,
+    ScopedJson,
+};
+use miette::{bail, IntoDiagnostic, Result};
+use mlua::{Function, Lua, LuaSerdeExt, MultiValue, SerializeOptions, Table};
+
+use crate::{config::SiloConfig, scripting::create_lua, utils::Describe};
+
+/// A helper that calls a lua function with the params followed
+/// by a table of the hash and renders the returned string.
+/// All helpers of a script share one lua state behind a mutex,
+/// so templates that call them don't render in parallel with each other
+pub struct LuaHelper {
+    name: String,
+    lua: Arc<Mutex<Lua>>,
+}
+
+/// The helper functions returned in the `helpers` table of the repo's
+/// `silo.config.lua` together with the lua state the config was evaluated in,
+/// so the script doesn't have to be evaluated again
+#[derive(Clone, Debug)]
+pub struct ScriptHelpers {
+    lua: Arc<Mutex<Lua>>,
+    names: Vec<String>,
+}
+
+impl ScriptHelpers {
+    /// Stores the functions of the `helpers` table in the registry
+    /// of the lua state and returns their names
+    pub fn store(lua: &Lua, helpers: Table<'_>, path: &Path) -> Result<Vec<String>> {
+        store_functions(lua, helpers, path)
+    }
+
+    /// Keeps the lua state that the named helpers were stored in
+    pub fn new(lua: Lua, names: Vec<String>) -> Self {
+        Self {
+            lua: Arc::new(Mutex::new(lua)),
+            names,
+        }
+    }
+}
+
+impl HelperDef for LuaHelper {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        h: &Helper<'rc>,
+        _: &'reg Handlebars<'reg>,
+        _: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'rc>, RenderError> {
+        let lua = self
+            .lua
+            .lock()
+            .map_err(|_| RenderErrorReason::Other(String::from("lua helper state is poisoned")))?;
+        let result = self.call_function(&lua, h).map_err(|e| {
+            RenderErrorReason::Other(format!("calling lua helper `{}`: {e}", self.name))
+        })?;
+
+        Ok(ScopedJson::Derived(serde_json::Value::String(result)))
+    }
+}
+
+impl LuaHelper {
+    fn call_function(&self, lua: &Lua, h: &Helper<'_>) -> mlua::Result<String> {
+        let options = SerializeOptions::new()
+            .serialize_none_to_null(false)
+            .serialize_unit_to_null(false);
+        let function: Function = lua.named_registry_value(&registry_name(&self.name))?;
+        let mut args = h
+            .params()
+            .iter()
+            .map(|p| lua.to_value_with(p.value(), options))
+            .collect::<mlua::Result<Vec<_>>>()?;
+        let hash: BTreeMap<_, _> = h.hash().iter().map(|(k, v)| (*k, v.value())).collect();
+        args.push(lua.to_value_with(&hash, options)?);
+
+        let result: Option<String> = function.call(MultiValue::from_vec(args))?;
+
+        Ok(result.unwrap_or_default())
+    }
+}
+
+/// Loads the helpers defined in the `helpers` directory of the repo and
+/// the helpers that were returned by the repo's `silo.config.lua`.
+/// A helper script either returns a function that is named after the file
+/// or a table of named functions
+pub fn load(repo: &Path, config: &SiloConfig) -> Result<Vec<(String, LuaHelper)>> {
+    let helper_dir = repo.join("helpers");
+    let mut scripts = Vec::new();
+
+    if helper_dir.is_dir() {
+        for entry in fs::read_dir(&helper_dir)
+            .with_describe(|| format!("reading directory {helper_dir:?}"))?
+        {
+            let path = entry.into_diagnostic()?.path();
+
+            if path.extension().is_some_and(|e| e == "lua") {
+                scripts.push(path);
+            }
+        }
+        scripts.sort();
+    }
+    let mut helpers = Vec::new();
+
+    if !scripts.is_empty() {
+        let lua = create_lua(config)?;
+        let mut names = Vec::new();
+
+        for path in scripts {
+            let value: mlua::Value = lua
+                .load(path.as_path())
+                .eval()
+                .with_describe(|| format!("evaluating helper script {path:?}"))?;
+
+            match value {
+                mlua::Value::Function(function) => {
+                    let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+                    store(&lua, &name, function, &path)?;
+                    names.push(name);
+                }
+                mlua::Value::Table(table) => names.extend(store_functions(&lua, table, &path)?),
+                _ => bail!(
+                    help = "return a function or a table of functions",
+                    "The helper script {path:?} doesn't return a function"
+                ),
+            }
+        }
+        helpers.push(ScriptHelpers::new(lua, names));
+    }
+    helpers.extend(config.script_helpers.clone());
+
+    Ok(helpers
+        .into_iter()
+        .flat_map(|helpers| {
+            let lua = helpers.lua;
+            helpers.names.into_iter().map(move |name| {
+                log::debug!("Registering lua helper {name}");
+                let helper = LuaHelper {
+                    name: name.clone(),
+                    lua: lua.clone(),
+                };
+                (name, helper)
+            })
+        })
+        .collect())
+}
+
+fn store_functions(lua: &Lua, table: Table<'_>, path: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+
+    for pair in table.pairs::<String, Function>() {
+        let (name, function) =
+            pair.with_describe(|| format!("reading helper functions of {path:?}"))?;
+        store(lua, &name, function, path)?;
+        names.push(name);
+    }
+
+    Ok(names)
+}
+
+fn store(lua: &Lua, name: &str, function: Function<'_>, path: &Path) -> Result<()> {
+    lua.set_named_registry_value(&registry_name(name), function)
+        .with_describe(|| format!("storing helper function of {path:?}"))
+}
+
+fn registry_name(name: &str) -> String {
+    format!("silo_helper_{name}")
+}
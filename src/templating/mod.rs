@@ -1,34 +1,202 @@
-use std::{env, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env, fs,
+    path::{Path, PathBuf},
+};
 
-use handlebars::Handlebars;
-use handlebars_switch::SwitchHelper;
+use handlebars::{Handlebars, RenderError, RenderErrorReason, Template};
 use lazy_static::lazy_static;
-use miette::{Context, IntoDiagnostic, Result};
+use miette::{IntoDiagnostic, Result};
+use rayon::prelude::*;
 use serde::Serialize;
+
+use crate::{config::SiloConfig, utils::Describe};
+
+mod error;
 mod helpers;
+mod lua_helpers;
+
+pub use error::TemplateError;
+pub use lua_helpers::ScriptHelpers;
+
+/// Renders a single template with a new engine
+pub fn render<T: Serialize + Clone>(
+    template: &str,
+    path: &Path,
+    ctx: T,
+    config: &SiloConfig,
+) -> Result<String> {
+    Engine::new(config).render(template, path, ctx)
+}
+
+/// A handlebars registry with all helpers that is created once per apply.
+/// Template files are compiled once and registered under their path
+pub struct Engine {
+    hb: Handlebars<'static>,
+    profile: Option<String>,
+    sources: HashMap<PathBuf, String>,
+    /// The files of the registered partials by name
+    partials: HashMap<String, PathBuf>,
+}
+
+impl Engine {
+    pub fn new(config: &SiloConfig) -> Self {
+        let mut hb = Handlebars::new();
+        hb.set_strict_mode(config.strict_templates);
+        helpers::register(&mut hb);
+
+        Self {
+            hb,
+            profile: config.profile.clone(),
+            sources: HashMap::new(),
+            partials: HashMap::new(),
+        }
+    }
+
+    /// Registers the lua helpers defined in the repo
+    pub fn register_lua_helpers(&mut self, repo: &Path, config: &SiloConfig) -> Result<()> {
+        for (name, helper) in lua_helpers::load(repo, config)? {
+            self.hb.register_helper(&name, Box::new(helper));
+        }
+
+        Ok(())
+    }
+
+    /// Registers the helpers that include and render files of the repo
+    pub fn register_file_helpers(&mut self, repo: &Path) {
+        helpers::register_file_helpers(&mut self.hb, repo);
+    }
+
+    /// Registers every file in the directory as a partial named
+    /// after its relative path without the extension
+    pub fn register_partials(&mut self, dir: &Path) -> Result<()> {
+        if !dir.is_dir() {
+            return Ok(());
+        }
+        let mut files = Vec::new();
+        collect_files(dir, &mut files)?;
+
+        for path in files {
+            let name = path
+                .strip_prefix(dir)
+                .into_diagnostic()?
+                .with_extension("")
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            let source =
+                fs::read_to_string(&path).with_describe(|| format!("reading partial {path:?}"))?;
+            log::debug!("Registering partial {name} from {path:?}");
+
+            self.hb.register_partial(&name, &source).map_err(|e| {
+                TemplateError::new(&path, &source, RenderErrorReason::from(e).into())
+            })?;
+            self.sources.insert(path.clone(), source);
+            self.partials.insert(name, path);
+        }
+
+        Ok(())
+    }
+
+    /// Renders a template string that was read from the given path
+    pub fn render<T: Serialize + Clone>(
+        &self,
+        template: &str,
+        path: &Path,
+        ctx: T,
+    ) -> Result<String> {
+        helpers::with_entry_file(path, || {
+            self.hb
+                .render_template(template, &context(ctx, self.profile.as_deref()))
+        })
+        .map_err(|e| self.error(path, template, e))
+    }
+
+    /// Reads and compiles the template files in parallel
+    /// and registers the ones that aren't registered yet
+    pub fn register_files(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let templates = paths
+            .par_iter()
+            .filter(|path| !self.sources.contains_key(*path))
+            .map(|path| {
+                let source = fs::read_to_string(path)
+                    .with_describe(|| format!("reading template {path:?}"))?;
+                let template = Template::compile(&source).map_err(|e| {
+                    TemplateError::new(path, &source, RenderErrorReason::from(e).into())
+                })?;
+
+                Ok((path.to_owned(), source, template))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (path, source, template) in templates {
+            self.hb.register_template(&template_name(&path), template);
+            self.sources.insert(path, source);
+        }
+
+        Ok(())
+    }
+
+    /// Renders the registered template files in parallel
+    /// and returns their contents in the same order
+    pub fn render_files<T: Serialize + Clone>(
+        &self,
+        paths: &[PathBuf],
+        ctx: T,
+    ) -> Result<Vec<String>> {
+        let ctx = handlebars::Context::wraps(context(ctx, self.profile.as_deref()))
+            .describe("serializing template context")?;
+
+        paths
+            .par_iter()
+            .map(|path| {
+                helpers::with_entry_file(path, || {
+                    self.hb.render_with_context(&template_name(path), &ctx)
+                })
+                .map_err(|e| {
+                    let source = self.sources.get(path).map_or("", String::as_str);
+                    self.error(path, source, e)
+                })
+            })
+            .collect()
+    }
+
+    /// Points the error at the partial it occurred in
+    /// or at the rendered template otherwise
+    fn error(&self, path: &Path, template: &str, err: RenderError) -> miette::Report {
+        let partial = err
+            .template_name
+            .as_ref()
+            .and_then(|name| self.partials.get(name))
+            .and_then(|path| Some((path, self.sources.get(path)?)));
+
+        match partial {
+            Some((path, source)) => TemplateError::new(path, source, err).into(),
+            None => TemplateError::new(path, template, err).into(),
+        }
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir).with_describe(|| format!("reading directory {dir:?}"))? {
+        let path = entry.into_diagnostic()?.path();
+
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
 
-pub fn render<T: Serialize + Clone>(template: &str, ctx: T) -> Result<String> {
-    engine()
-        .render_template(template, &context(ctx))
-        .into_diagnostic()
-        .context("rendering to path")
+    Ok(())
 }
 
-fn engine<'a>() -> Handlebars<'a> {
-    let mut hb = Handlebars::new();
-    hb.register_helper("switch", Box::new(SwitchHelper));
-    hb.register_helper(
-        "if-installed",
-        Box::new(helpers::IfInstalledHelper { positive: true }),
-    );
-    hb.register_helper(
-        "if-not-installed",
-        Box::new(helpers::IfInstalledHelper { positive: false }),
-    );
-    hb
+fn template_name(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
 }
 
-pub fn context<'a, T: Serialize + Clone>(cfg: T) -> WrappedContext<'a, T> {
+pub fn context<'a, T: Serialize + Clone>(cfg: T, profile: Option<&str>) -> WrappedContext<'a, T> {
     lazy_static! {
         static ref CTX: ContextData = ContextData::default();
     }
@@ -36,6 +204,7 @@ pub fn context<'a, T: Serialize + Clone>(cfg: T) -> WrappedContext<'a, T> {
         data: &CTX,
         ctx: cfg.clone(),
         cfg,
+        profile: profile.map(String::from),
     }
 }
 
@@ -45,6 +214,7 @@ pub struct WrappedContext<'a, T: Serialize> {
     data: &'a ContextData,
     ctx: T,
     cfg: T,
+    profile: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Default)]
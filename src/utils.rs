@@ -1,4 +1,7 @@
-use std::fmt;
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+};
 
 use miette::{Context, IntoDiagnostic};
 
@@ -25,3 +28,22 @@ impl<T, E: std::error::Error + Send + Sync + 'static> Describe<T, E> for Result<
         }
     }
 }
+
+/// Resolves `.` and `..` components without accessing the file system
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}